@@ -56,10 +56,32 @@ Blockly.Extensions.register('dynamic_options',
     this.inputList[0].appendField(dropdown, 'Key')
   });
 
+// Replaces the text editor of the `Date` field with the browser's date and time picker
+Blockly.Extensions.register('date_picker',
+  function() {
+    var field = this.getField('Date');
+    field.showEditor_ = function() {
+        Blockly.DropDownDiv.clearContent();
+
+        var input = document.createElement('input');
+        input.type = 'datetime-local';
+        input.value = field.getValue().trim().replace(' ', 'T');
+        input.addEventListener('change', function() {
+            if (input.value) {
+                field.setValue(input.value.replace('T', ' '));
+            }
+        });
+        Blockly.DropDownDiv.getContentDiv().appendChild(input);
+
+        Blockly.DropDownDiv.showPositionedByField(field, function() {});
+        input.focus();
+    };
+  });
+
 window.BlocklyExt = {};
 window.BlocklyExt.initJs = function(workspace, compiled_cb, dropdown_cb) {
     function myUpdateFunction(event) {
-        compiled_cb(Blockly.JavaScript.workspaceToCode(workspace));
+        compiled_cb(Blockly.serialization.workspaces.save(workspace));
     }
     workspace.addChangeListener(myUpdateFunction);
     workspace.addChangeListener(Blockly.Events.disableOrphans);
@@ -98,115 +120,3 @@ window.BlocklyExt.insertBegin = function(workspace, compiled_cb, dropdown_cb) {
     beginBlock.initSvg();
     beginBlock.render();
 }
-window.BlocklyExt.saveBlockly = function(workspace) {
-    BlocklyStorage.backupBlocks_(workspace);
-}
-
-Blockly.JavaScript.INDENT = '';
-Blockly.JavaScript['begin'] = function(block) {
-  return '';
-};
-
-Blockly.JavaScript['pk'] = function(block) {
-    if (!block.getParent()) {
-        return '';
-    }
-
-  var value_pk = Blockly.JavaScript.valueToCode(block, 'Key', Blockly.JavaScript.ORDER_ATOMIC);
-    if (value_pk == '') {
-        value_pk = '()';
-    }
-
-  var code = 'pk' + value_pk;
-  return code;
-};
-
-Blockly.JavaScript['key'] = function(block) {
-    if (!block.getParent()) {
-        return ['', Blockly.JavaScript.ORDER_NONE];
-    }
-
-  var text_key = block.getFieldValue('Key');
-  var code = text_key;
-  // TODO: Change ORDER_NONE to the correct strength.
-  return [code, Blockly.JavaScript.ORDER_NONE];
-};
-
-Blockly.JavaScript['my_key'] = function(block) {
-    if (!block.getParent()) {
-        return ['', Blockly.JavaScript.ORDER_NONE];
-    }
-
-  // TODO: Change ORDER_NONE to the correct strength.
-  return ['_MY_KEY', Blockly.JavaScript.ORDER_NONE];
-};
-
-Blockly.JavaScript['thresh'] = function(block) {
-  var number_threshold = block.getFieldValue('Threshold');
-  var code = 'thresh(' + number_threshold + ',';
-  var child = block.getChildren(true)[0];
-  while (child) {
-    code += Blockly.JavaScript[child.type](child);
-    child = child.getNextBlock();
-    if (child) {
-      code += ',';
-    }
-  }
-  code += ')';
-  return code;
-};
-
-Blockly.JavaScript['older'] = function(block) {
-    if (!block.getParent()) {
-        return '';
-    }
-
-  var number_name = block.getFieldValue('value');
-  var code = 'older(' + number_name + ')';
-  return code;
-};
-
-Blockly.JavaScript['after'] = function(block) {
-    if (!block.getParent()) {
-        return '';
-    }
-
-  var number_name = block.getFieldValue('value');
-  // TODO: Assemble JavaScript into code variable.
-  var code = 'after(' + number_name + ')';
-  return code;
-};
-
-Blockly.JavaScript['and'] = function(block) {
-    if (!block.getParent()) {
-        return '';
-    }
-
-  var statements_a = Blockly.JavaScript.statementToCode(block, 'A');
-  var statements_b = Blockly.JavaScript.statementToCode(block, 'B');
-  var code = 'and(' + statements_a + ',' + statements_b + ')';
-  return code;
-};
-
-Blockly.JavaScript['or'] = function(block) {
-    if (!block.getParent()) {
-        return '';
-    }
-
-  var number_a_weight = block.getFieldValue('A_weight');
-    if (number_a_weight == '1') {
-        number_a_weight = '';
-    } else {
-        number_a_weight = number_a_weight + '@';
-    }
-  var statements_a = Blockly.JavaScript.statementToCode(block, 'A');
-  var number_b_weight = block.getFieldValue('B_weight');
-    if (number_b_weight == '1') {
-        number_b_weight = '';
-    } else {
-        number_b_weight = number_b_weight + '@';
-    }
-  var statements_b = Blockly.JavaScript.statementToCode(block, 'B');
-  var code = 'or(' + number_a_weight + statements_a + ',' + number_b_weight + statements_b + ')';
-  return code;
-};
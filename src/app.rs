@@ -1,30 +1,95 @@
 use crate::AppWallet;
+use bdk::bitcoin;
+use bdk::bitcoin::psbt::Psbt;
+use bdk::bitcoin::Txid;
 use bdk::miniscript::policy::Concrete;
-use bdk::{bitcoin, Balance};
+use gloo_timers::callback::{Interval, Timeout};
+use std::collections::HashMap;
 use std::str::FromStr;
+use wasm_bindgen::closure::Closure;
+use wasm_bindgen::JsCast;
+use web_sys::HtmlSelectElement;
 use yew::prelude::*;
 use yew_agent::{Bridge, Bridged};
 
-use crate::evt::EventBus;
+use crate::app_wallet::BackendSettings;
+use crate::compiler::{Compilation, CompileRequest, CompileResponse, CompileWorker, Compiled};
+use crate::evt::{AppEvent, EventBus};
+use crate::keymanager::encryption;
+use crate::psbt_file::parse_psbt_str;
+use crate::psbt_workspace::{PsbtWorkspace, PsbtWorkspaceView};
+use crate::route::{self, Route};
+use crate::tracker::{self, PollResult, TrackedTx, TxState};
+use crate::wallets::{WalletDescriptors, Wallets};
 
 pub struct App {
-    wallet: Option<AppWallet>,
-    _recv: Box<dyn Bridge<EventBus>>,
+    wallets: Wallets,
+    /// Backend of the project open in the wallet creator, used to build the draft
+    settings: BackendSettings,
+    bus: Box<dyn Bridge<EventBus>>,
+    /// Current wallet as last announced on the bus
+    loaded: Option<usize>,
+    /// PSBTs of the current wallet
+    psbts: PsbtWorkspace,
     current_tab: Tabs,
-    is_loading: bool,
-    balance: bdk::Balance,
-    transactions: Vec<(String, i64)>,
+    /// Route opened before there was a wallet to show it with
+    pending_route: Option<Route>,
+    /// Listens to the back and forward buttons of the browser
+    _popstate_cb: Closure<dyn Fn()>,
+    /// Postpones the auto-lock of the keys on every click or key press, in any tab
+    _activity_cb: Closure<dyn Fn()>,
+    _auto_lock: Interval,
+    /// Policies compiled so far, by normalized policy
+    compiled: HashMap<String, Result<Compiled, String>>,
+    /// Latest policy received, while waiting for the edits to settle
+    pending_policy: Option<String>,
+    policy_debounce: Option<Timeout>,
+    compiler: Box<dyn Bridge<CompileWorker>>,
+    /// Id of the latest compile request, the responses to older ones are stale
+    compile_id: u64,
+    compilation: Compilation,
+
+    tracked: Vec<TrackedTx>,
+    tip: u32,
+    is_polling: bool,
+    poll_interval: Option<Interval>,
 }
 
 pub enum Msg {
-    ReloadTriggered,
-    ReloadFinished,
+    ReloadTriggered(usize),
+    ReloadFinished(usize, Result<(), String>),
     TabChange(Tabs),
+    /// The URL changed, with the back or forward buttons or a link
+    RouteChanged,
     Descriptor(String),
+    SettingsChanged(BackendSettings),
+    PolicySettled,
+    PolicyCompiled(CompileResponse),
+
+    WalletSelected(usize),
+    KeepWallet(String),
+    CloseWallet(usize),
+    AutoLockCheck,
+    /// The keys have been locked, the wallets that can sign are closed
+    Locked,
+
+    /// A PSBT created, signed or merged, and whether it goes to the merge tab as well
+    PsbtReceived(Psbt, bool),
+    SignPsbt(Txid),
+    MergePsbt(Txid),
+    RemovePsbt(Txid),
+    MergeQueued(Psbt),
+    MergeUnqueued(usize),
+
+    Broadcasted(Txid),
+    PollTriggered,
+    PollFinished(Result<PollResult, String>),
+    DismissTracked(Txid),
 }
 
-#[derive(Copy, Clone)]
+#[derive(Copy, Clone, Debug, PartialEq)]
 pub enum Tabs {
+    Portfolio,
     Home,
     KeyManagement,
     CreateTx,
@@ -32,131 +97,668 @@ pub enum Tabs {
     Merge,
 }
 
-fn parse_policy(policy: &str) -> Result<AppWallet, Box<dyn std::error::Error>> {
-    let policy = Concrete::<String>::from_str(policy)?;
-    let policy: bdk::miniscript::Miniscript<String, bdk::miniscript::Tap> = policy.compile()?;
+/// Internal key used for wallets compiled from a policy, nobody knows the private key for it
+pub const UNSPENDABLE_KEY: &str =
+    "89de7c56ecdf6c400295a57a203d87a53ed28f74735d2373a3e034781338f259";
+
+/// Time to wait for the policy to stop changing before building the wallet, in ms
+const POLICY_DEBOUNCE_MS: u32 = 300;
+/// Number of compiled policies kept in memory
+const COMPILED_CACHE_SIZE: usize = 64;
+/// How often the encryption session is checked for inactivity, in ms
+const AUTO_LOCK_CHECK_MS: u32 = 30_000;
 
-    Ok(AppWallet::new(
-        &format!(
-            "tr(89de7c56ecdf6c400295a57a203d87a53ed28f74735d2373a3e034781338f259,{})",
-            policy
-        ),
-        None,
+/// Normalizes a policy, so that moving blocks around doesn't look like a new policy. Descriptors
+/// are only trimmed.
+fn normalize_policy(policy: &str) -> String {
+    let policy = policy.trim();
+    match Concrete::<String>::from_str(policy) {
+        Ok(policy) => policy.to_string(),
+        Err(_) => policy.to_string(),
+    }
+}
+
+fn build_wallet(
+    descriptors: &WalletDescriptors,
+    settings: &BackendSettings,
+) -> Result<AppWallet, bdk::Error> {
+    AppWallet::new(
+        descriptors.external.as_str(),
+        descriptors.internal.as_deref(),
         bitcoin::Network::Testnet,
-    )?)
+        settings,
+    )
 }
 
 impl App {
-    fn create_tab(&self) -> Html {
+    fn wallet(&self) -> Option<&AppWallet> {
+        self.wallets.current().map(|w| &w.wallet)
+    }
+
+    fn create_tab(&self, ctx: &Context<Self>) -> Html {
         match self.current_tab {
+            Tabs::Portfolio => html! {
+                <crate::portfolio::Portfolio
+                    wallets={self.wallets.list().to_vec()}
+                    current={self.wallets.current_id()}
+                    draft={self.wallets.draft()}
+                    on_open={ctx.link().callback(Msg::WalletSelected)}
+                    on_sync={ctx.link().callback(Msg::ReloadTriggered)}
+                    on_close={ctx.link().callback(Msg::CloseWallet)}
+                    on_keep={ctx.link().callback(Msg::KeepWallet)}
+                />
+            },
             Tabs::Home => {
-                html! { < crate::home::Home wallet={self.wallet.as_ref().unwrap().clone()} transactions = {self.transactions.clone()} balance = {self.balance.clone()} /> }
+                let current = self.wallets.current().unwrap();
+                html! { < crate::home::Home wallet={current.wallet.clone()} transactions = {current.history()} balance = {current.balance.clone()} /> }
+            }
+            Tabs::KeyManagement => {
+                html! {< crate::keymanager::Keymanager compilation={self.compilation.clone()} />}
             }
-            Tabs::KeyManagement => html! {< crate::keymanager::Keymanager />},
             Tabs::CreateTx => {
-                html! { < crate::tab_create_tx::TabCreateTx wallet={self.wallet.as_ref().unwrap().clone()} /> }
+                html! { < crate::tab_create_tx::TabCreateTx wallet={self.wallet().unwrap().clone()} /> }
             }
             Tabs::SignTx => {
-                html! { < crate::sign::Sign wallet={self.wallet.as_ref().unwrap().clone()} /> }
+                html! { < crate::sign::Sign wallet={self.wallet().unwrap().clone()} psbt={self.psbts.to_sign().cloned()} /> }
             }
-            Tabs::Merge => {
-                html! { < crate::merge::Merge wallet={self.wallet.as_ref().unwrap().clone()} /> }
+            Tabs::Merge => html! {
+                <crate::merge::Merge
+                    wallet={self.wallet().unwrap().clone()}
+                    psbts={self.psbts.to_merge.clone()}
+                    on_add={ctx.link().callback(Msg::MergeQueued)}
+                    on_remove={ctx.link().callback(Msg::MergeUnqueued)}
+                />
+            },
+        }
+    }
+
+    /// Transactions in progress, below the tabs working on them
+    fn psbt_workspace(&self, ctx: &Context<Self>) -> Html {
+        match (self.current_tab, self.wallet()) {
+            (Tabs::CreateTx | Tabs::SignTx | Tabs::Merge, Some(wallet)) => html! {
+                <PsbtWorkspaceView
+                    wallet={wallet.clone()}
+                    workspace={self.psbts.clone()}
+                    on_sign={ctx.link().callback(Msg::SignPsbt)}
+                    on_merge={ctx.link().callback(Msg::MergePsbt)}
+                    on_remove={ctx.link().callback(Msg::RemovePsbt)}
+                />
+            },
+            _ => html! {},
+        }
+    }
+
+    /// Opens the wallet of the policy designed in the wallet creator as the draft, compiling the
+    /// policy in the worker unless it's cached. Returns `false` if nothing changed.
+    fn load_policy(&mut self, policy: &str) -> bool {
+        // Whatever the worker is compiling is stale now
+        self.compile_id += 1;
+
+        let policy = normalize_policy(policy);
+        if policy.is_empty() {
+            self.compilation = Compilation::Idle;
+            self.wallets.clear_draft();
+            return true;
+        }
+
+        match self.compiled.get(&policy) {
+            Some(compiled) => self.open_compiled(policy, compiled.clone()),
+            None => {
+                self.compilation = Compilation::Running;
+                self.compiler.send(CompileRequest {
+                    id: self.compile_id,
+                    policy,
+                });
+                true
             }
         }
     }
-}
 
-impl Component for App {
-    type Message = Msg;
-    type Properties = ();
+    fn open_compiled(&mut self, policy: String, compiled: Result<Compiled, String>) -> bool {
+        let compilation = Compilation::Done(compiled.clone().map(|c| c.weights));
+        let changed = self.compilation != compilation;
+        self.compilation = compilation;
 
-    fn create(ctx: &Context<Self>) -> Self {
-        App {
-            is_loading: false,
-            wallet: None,
-            _recv: EventBus::bridge(ctx.link().callback(Msg::Descriptor)),
-            current_tab: Tabs::KeyManagement,
-            balance: Balance::default(),
-            transactions: vec![],
+        let settings = self.settings.clone();
+        let draft = self.wallets.draft().and_then(|id| self.wallets.get(id));
+        if draft.is_some_and(|d| d.source == policy && d.settings == settings) {
+            return changed;
         }
+
+        let descriptors = match compiled {
+            Ok(compiled) => compiled.descriptors,
+            Err(e) => {
+                log::warn!("{}", e);
+                self.wallets.clear_draft();
+                return true;
+            }
+        };
+
+        // Another policy compiling to the same descriptor, or a wallet that's already open: keep
+        // its sync data instead of building it again
+        if let Some(id) = self.wallets.find(&descriptors, &settings) {
+            if self.wallets.draft() == Some(id) {
+                if let Some(draft) = self.wallets.get_mut(id) {
+                    draft.source = policy;
+                }
+            } else {
+                self.wallets.clear_draft();
+            }
+            self.wallets.select(id);
+            return true;
+        }
+
+        match build_wallet(&descriptors, &settings) {
+            Ok(w) => self.wallets.set_draft(policy, descriptors, settings, w),
+            Err(e) => {
+                log::warn!("{:?}", e);
+                self.wallets.clear_draft();
+            }
+        }
+
+        true
     }
 
-    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+    /// Leaves the tabs that need a wallet, or a wallet that can sign, when there's none, and
+    /// tells the other components when the current wallet changes. Opens the route waiting for
+    /// a wallet once there's one.
+    fn check_tab(&mut self) {
+        let current = self.wallets.current_id();
+        if current != self.loaded {
+            self.loaded = current;
+            self.psbts = self.wallet().map(PsbtWorkspace::load).unwrap_or_default();
+            self.bus.send(AppEvent::WalletLoaded(current));
+        }
+
+        if self.wallet().is_some() {
+            if let Some(route) = self.pending_route.take() {
+                self.open_route(route);
+            }
+        }
+
+        self.current_tab = match (self.current_tab, self.wallet()) {
+            (Tabs::Portfolio | Tabs::KeyManagement, _) => self.current_tab,
+            (_, None) => Tabs::KeyManagement,
+            (Tabs::SignTx, Some(w)) if !w.can_sign() => Tabs::Home,
+            (tab, Some(_)) => tab,
+        };
+    }
+
+    /// Opens the tab of a route, with the PSBT or transaction in its parameters. Routes needing a
+    /// wallet wait for one.
+    fn open_route(&mut self, route: Route) {
+        if self.wallet().is_none() && !matches!(route.tab, Tabs::KeyManagement | Tabs::Portfolio) {
+            self.pending_route = Some(route);
+            return;
+        }
+
+        match route.param("psbt").map(parse_psbt_str) {
+            Some(Ok(psbt)) => {
+                let txid = psbt.unsigned_tx.txid();
+                if matches!(route.tab, Tabs::Merge) {
+                    self.psbts.queue_merge(psbt.clone());
+                }
+                self.psbts.add(psbt);
+                self.psbts.select_to_sign(txid);
+            }
+            Some(Err(e)) => log::warn!("Invalid PSBT in the URL: {}", e),
+            None => {}
+        }
+        if let Some(txid) = route.param("txid") {
+            match Txid::from_str(txid) {
+                Ok(txid) if self.psbts.contains(txid) => self.psbts.select_to_sign(txid),
+                Ok(txid) => log::warn!("Transaction {} isn't in progress", txid),
+                Err(e) => log::warn!("Invalid txid in the URL: {}", e),
+            }
+        }
+
+        self.current_tab = route.tab;
+    }
+
+    /// Shows the current tab in the address bar. Tabs opened by the user get a new entry in the
+    /// browser history, the others replace the current one.
+    fn update_route(&self, replace: bool) {
+        // Keep the deep link until the wallet is there to open it
+        if self.pending_route.is_some() {
+            return;
+        }
+        let current = route::current();
+        // A deep link is opened once: replace it, so that a reload doesn't import its PSBT again
+        let is_deep_link = current
+            .as_ref()
+            .is_some_and(|r| r.param("psbt").is_some() || r.param("txid").is_some());
+        if is_deep_link {
+            route::navigate(&Route::new(self.current_tab), true);
+        } else if current.map(|r| r.tab) != Some(self.current_tab) {
+            route::navigate(&Route::new(self.current_tab), replace);
+        }
+    }
+
+    /// Builds the wallet again after the edits settle, dropping the previous timeout cancels it
+    fn debounce_policy(&mut self, ctx: &Context<Self>) {
+        let link = ctx.link().clone();
+        self.policy_debounce = Some(Timeout::new(POLICY_DEBOUNCE_MS, move || {
+            link.send_message(Msg::PolicySettled)
+        }));
+    }
+
+    fn wallet_switcher(&self, ctx: &Context<Self>) -> Html {
+        if self.wallets.list().len() < 2 {
+            return html! {};
+        }
+
+        let onchange = ctx.link().batch_callback(|e: Event| {
+            let value = e.target_unchecked_into::<HtmlSelectElement>().value();
+            value.parse().ok().map(Msg::WalletSelected)
+        });
+        let current = self.wallets.current_id();
+
+        html! {
+            <select class="form-select form-select-sm w-auto ms-3" {onchange}>
+                { for self.wallets.list().iter().map(|w| html! {
+                    <option value={w.id.to_string()} selected={current == Some(w.id)}>{ &w.name }</option>
+                })}
+            </select>
+        }
+    }
+
+    /// Progress of the syncs, and the error of the last sync of the current wallet
+    fn sync_status(&self) -> Html {
+        let current = self.wallets.current();
+        let others = self
+            .wallets
+            .list()
+            .iter()
+            .filter(|w| w.is_loading && Some(w.id) != self.wallets.current_id())
+            .count();
+        let status = match current {
+            Some(w) if w.is_loading => html! {
+                <span><span class="spinner-border spinner-border-sm me-1" role="status"></span>{ "Syncing..." }</span>
+            },
+            Some(w) => match (&w.sync_error, w.synced_height) {
+                (Some(e), _) => html! {
+                    <span class="text-danger text-truncate d-inline-block" style="max-width: 300px" title={e.clone()}>{ format!("Sync failed: {}", e) }</span>
+                },
+                (None, Some(height)) => {
+                    html! { <span>{ format!("Synced at block {}", height) }</span> }
+                }
+                (None, None) => html! { <span>{ "Not synced" }</span> },
+            },
+            None => html! {},
+        };
+
+        html! {
+            <div class="d-flex align-items-center small text-muted ms-3 me-3">
+                { status }
+                if others > 0 {
+                    <span class="ms-2">{ format!("({} other wallet(s) syncing)", others) }</span>
+                }
+            </div>
+        }
+    }
+
+    fn tracked_notifications(&self, ctx: &Context<Self>) -> Html {
+        html! {
+            <div class="container">
+            { for self.tracked.iter().map(|tx| {
+                let txid = tx.txid;
+                let dismiss = ctx.link().callback(move |_| Msg::DismissTracked(txid));
+                let class = match tx.state {
+                    TxState::Confirmed { .. } => "alert-success",
+                    _ => "alert-info",
+                };
+                html! {
+                    <div class={classes!("alert", "alert-dismissible", "tracked-tx", class)} role="alert">
+                        { tx.description(self.tip) }
+                        <button type="button" class="btn-close" onclick={dismiss}></button>
+                    </div>
+                }
+            })}
+            </div>
+        }
+    }
+
+    /// Starts polling if there's anything left to track, stops it otherwise
+    fn update_polling(&mut self, ctx: &Context<Self>) {
+        let needs_polling = self.tracked.iter().any(|tx| !tx.is_done(self.tip));
+        match (needs_polling, &self.poll_interval) {
+            (true, None) => {
+                let link = ctx.link().clone();
+                self.poll_interval = Some(Interval::new(tracker::POLL_INTERVAL_MS, move || {
+                    link.send_message(Msg::PollTriggered)
+                }));
+            }
+            (false, Some(_)) => {
+                self.poll_interval = None;
+            }
+            _ => {}
+        }
+    }
+
+    /// Handles a message, the route is updated afterwards
+    fn handle(&mut self, ctx: &Context<Self>, msg: Msg) -> bool {
         match msg {
             Msg::Descriptor(s) => {
-                match parse_policy(&s) {
-                    Ok(w) => {
-                        self.wallet = Some(w);
-                    }
-                    Err(e) => {
-                        log::warn!("{:?}", e);
-                        self.wallet = None;
-                    }
+                // Blockly sends the policy on every change, wait for the edits to settle
+                self.pending_policy = Some(s);
+                self.debounce_policy(ctx);
+                false
+            }
+            Msg::SettingsChanged(settings) => {
+                if settings == self.settings {
+                    return false;
+                }
+                self.settings = settings;
+                // The draft is built again with the new settings, unless a new policy is coming
+                if self.pending_policy.is_none() {
+                    let draft = self.wallets.draft().and_then(|id| self.wallets.get(id));
+                    self.pending_policy = draft.map(|d| d.source.clone());
+                }
+                self.debounce_policy(ctx);
+                false
+            }
+            Msg::PolicySettled => {
+                self.policy_debounce = None;
+                let changed = match self.pending_policy.take() {
+                    Some(policy) => self.load_policy(&policy),
+                    None => false,
+                };
+                self.check_tab();
+
+                changed
+            }
+            Msg::PolicyCompiled(response) => {
+                if response.id != self.compile_id {
+                    return false;
                 }
+                if self.compiled.len() >= COMPILED_CACHE_SIZE {
+                    self.compiled.clear();
+                }
+                self.compiled
+                    .insert(response.policy.clone(), response.result.clone());
 
-                true
+                let changed = self.open_compiled(response.policy, response.result);
+                self.check_tab();
+
+                changed
             }
             Msg::TabChange(t) => {
+                self.pending_route = None;
                 self.current_tab = t;
                 true
             }
-            Msg::ReloadTriggered => {
-                self.is_loading = true;
-                let wallet_cloned = self.wallet.as_ref().unwrap().0.clone();
+            Msg::RouteChanged => {
+                match route::current() {
+                    Some(route) => self.open_route(route),
+                    None => self.current_tab = Tabs::KeyManagement,
+                }
+                self.check_tab();
+                true
+            }
+            Msg::WalletSelected(id) => {
+                self.wallets.select(id);
+                if matches!(self.current_tab, Tabs::Portfolio) {
+                    self.current_tab = Tabs::Home;
+                }
+                self.check_tab();
+                true
+            }
+            Msg::KeepWallet(name) => {
+                self.wallets.keep_draft(name);
+                true
+            }
+            Msg::CloseWallet(id) => {
+                self.wallets.close(id);
+                self.check_tab();
+                true
+            }
+            Msg::AutoLockCheck => {
+                if encryption::expire_session() {
+                    self.bus.send(AppEvent::Locked);
+                }
+                false
+            }
+            Msg::Locked => {
+                self.wallets.close_signing();
+                // Compiled policies and the pending one contain the private keys too, a policy
+                // still compiling is ignored when it's done
+                self.compiled.clear();
+                self.pending_policy = None;
+                self.policy_debounce = None;
+                self.compile_id += 1;
+                self.check_tab();
+                true
+            }
+            Msg::ReloadTriggered(id) => {
+                let wallet = match self.wallets.get_mut(id) {
+                    Some(w) if !w.is_loading => {
+                        w.is_loading = true;
+                        w.wallet.clone()
+                    }
+                    _ => return false,
+                };
+                self.bus.send(AppEvent::SyncStarted(id));
+                // The tabs keep reading the wallet meanwhile, the sync runs on a copy
                 ctx.link().send_future(async move {
-                    let _res = wallet_cloned
-                        .borrow()
-                        .0
-                        .sync(
-                            &wallet_cloned.borrow().1,
-                            bdk::wallet::SyncOptions::default(),
-                        )
-                        .await;
-                    Msg::ReloadFinished
+                    let res = wallet.sync().await;
+                    Msg::ReloadFinished(id, res)
                 });
                 true
             }
-            Msg::ReloadFinished => {
-                self.is_loading = false;
-                self.balance = self
-                    .wallet
-                    .as_ref()
-                    .unwrap()
-                    .borrow()
-                    .0
-                    .get_balance()
-                    .unwrap();
-                let mut temp_tx = self
-                    .wallet
-                    .as_ref()
-                    .unwrap()
-                    .borrow()
-                    .0
-                    .list_transactions(false)
-                    .unwrap();
-                temp_tx.sort_by(|a, b| {
-                    b.confirmation_time
-                        .as_ref()
-                        .map(|t| t.height)
-                        .cmp(&a.confirmation_time.as_ref().map(|t| t.height))
+            Msg::PsbtReceived(psbt, queue_merge) => {
+                if queue_merge {
+                    self.psbts.queue_merge(psbt.clone());
+                }
+                self.psbts.add(psbt);
+                true
+            }
+            Msg::SignPsbt(txid) => {
+                self.psbts.select_to_sign(txid);
+                self.current_tab = Tabs::SignTx;
+                true
+            }
+            Msg::MergePsbt(txid) => {
+                self.psbts.queue_merge_tx(txid);
+                self.current_tab = Tabs::Merge;
+                true
+            }
+            Msg::RemovePsbt(txid) => {
+                self.psbts.remove(txid);
+                true
+            }
+            Msg::MergeQueued(psbt) => {
+                self.psbts.queue_merge(psbt);
+                true
+            }
+            Msg::MergeUnqueued(i) => {
+                self.psbts.unqueue_merge(i);
+                true
+            }
+            Msg::Broadcasted(txid) => {
+                // The transaction isn't in progress anymore
+                self.psbts.remove(txid);
+                if !self.tracked.iter().any(|tx| tx.txid == txid) {
+                    self.tracked.push(TrackedTx::new(txid));
+                }
+                self.update_polling(ctx);
+                ctx.link().send_message(Msg::PollTriggered);
+                true
+            }
+            Msg::PollTriggered => {
+                let blockchain = match self.wallet() {
+                    Some(w) if !self.is_polling => w.blockchain(),
+                    _ => return false,
+                };
+                let tip = self.tip;
+                let txids = self
+                    .tracked
+                    .iter()
+                    .filter(|tx| !tx.is_done(tip))
+                    .map(|tx| tx.txid)
+                    .collect::<Vec<_>>();
+                if txids.is_empty() {
+                    return false;
+                }
+
+                self.is_polling = true;
+                ctx.link().send_future(async move {
+                    let res = tracker::poll(&blockchain, txids).await;
+                    Msg::PollFinished(res.map_err(|e| e.to_string()))
                 });
-                self.transactions = temp_tx
-                    .into_iter()
-                    .map(|tx| (tx.txid.to_string(), tx.received as i64 - tx.sent as i64))
-                    .collect();
+                false
+            }
+            Msg::PollFinished(Err(e)) => {
+                log::warn!("Failed to poll tracked transactions: {}", e);
+                self.is_polling = false;
+                false
+            }
+            Msg::PollFinished(Ok(res)) => {
+                self.is_polling = false;
+                self.tip = res.tip;
+
+                let mut newly_confirmed = false;
+                for (txid, state) in res.states {
+                    if let Some(tx) = self.tracked.iter_mut().find(|tx| tx.txid == txid) {
+                        if !matches!(tx.state, TxState::Confirmed { .. })
+                            && matches!(state, TxState::Confirmed { .. })
+                        {
+                            newly_confirmed = true;
+                        }
+                        tx.state = state;
+                    }
+                }
+                if newly_confirmed {
+                    // The transaction could belong to any of the open wallets
+                    for w in self.wallets.list() {
+                        ctx.link().send_message(Msg::ReloadTriggered(w.id));
+                    }
+                }
+                self.update_polling(ctx);
+                true
+            }
+            Msg::DismissTracked(txid) => {
+                self.tracked.retain(|tx| tx.txid != txid);
+                self.update_polling(ctx);
                 true
             }
+            Msg::ReloadFinished(id, res) => match self.wallets.get_mut(id) {
+                Some(w) => {
+                    match res {
+                        Ok(()) => w.refresh(),
+                        Err(e) => {
+                            log::warn!("Failed to sync {}: {}", w.name, e);
+                            w.sync_failed(e);
+                        }
+                    }
+                    self.bus.send(AppEvent::SyncFinished(id));
+                    true
+                }
+                // The wallet has been closed or replaced in the meantime
+                None => false,
+            },
+        }
+    }
+}
+
+impl Component for App {
+    type Message = Msg;
+    type Properties = ();
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let link = ctx.link().clone();
+        let popstate_cb = Closure::<dyn Fn()>::new(move || link.send_message(Msg::RouteChanged));
+        let _ = web_sys::window()
+            .unwrap()
+            .add_event_listener_with_callback("popstate", popstate_cb.as_ref().unchecked_ref());
+        let activity_cb = Closure::<dyn Fn()>::new(encryption::touch_session);
+        for event in ["pointerdown", "keydown"] {
+            let _ = web_sys::window()
+                .unwrap()
+                .add_event_listener_with_callback(event, activity_cb.as_ref().unchecked_ref());
         }
+        let link = ctx.link().clone();
+        let auto_lock = Interval::new(AUTO_LOCK_CHECK_MS, move || {
+            link.send_message(Msg::AutoLockCheck)
+        });
+
+        let mut app = App {
+            wallets: Wallets::default(),
+            settings: BackendSettings::default(),
+            bus: EventBus::bridge(ctx.link().batch_callback(|event| match event {
+                AppEvent::PolicyChanged(policy) => Some(Msg::Descriptor(policy)),
+                AppEvent::SettingsChanged(settings) => Some(Msg::SettingsChanged(settings)),
+                AppEvent::Broadcast(Ok(txid)) => Some(Msg::Broadcasted(txid)),
+                AppEvent::PsbtCreated(psbt) | AppEvent::PsbtSigned(psbt) => {
+                    Some(Msg::PsbtReceived(psbt, true))
+                }
+                AppEvent::PsbtMerged(psbt) => Some(Msg::PsbtReceived(psbt, false)),
+                AppEvent::Locked => Some(Msg::Locked),
+                _ => None,
+            })),
+            loaded: None,
+            psbts: PsbtWorkspace::default(),
+            current_tab: Tabs::KeyManagement,
+            pending_route: None,
+            _popstate_cb: popstate_cb,
+            _activity_cb: activity_cb,
+            _auto_lock: auto_lock,
+            compiled: HashMap::new(),
+            pending_policy: None,
+            policy_debounce: None,
+            compiler: CompileWorker::bridge(ctx.link().callback(Msg::PolicyCompiled)),
+            compile_id: 0,
+            compilation: Compilation::Idle,
+
+            tracked: vec![],
+            tip: 0,
+            is_polling: false,
+            poll_interval: None,
+        };
+        if let Some(route) = route::current() {
+            app.open_route(route);
+        }
+        app.update_route(true);
+
+        app
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        let is_navigation = matches!(
+            msg,
+            Msg::TabChange(_) | Msg::WalletSelected(_) | Msg::SignPsbt(_) | Msg::MergePsbt(_)
+        );
+        let changed = self.handle(ctx, msg);
+        self.update_route(!is_navigation);
+
+        changed
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
-        let disabled_link = match self.wallet {
+        let disabled_link = match self.wallet() {
             Some(_) => None,
             None => Some("disabled"),
         };
         let onclick = move |t: Tabs| ctx.link().callback(move |_| Msg::TabChange(t));
-        let onclick_load = ctx.link().callback(move |_| Msg::ReloadTriggered);
-        let disabled = self.is_loading || self.wallet.is_none();
+        let active = |t: Tabs| (self.current_tab == t).then_some("active");
+        let aria_current = |t: Tabs| (self.current_tab == t).then_some("page");
+        let current = self.wallets.current();
+        let is_loading = current.map(|w| w.is_loading).unwrap_or(false);
+        let onclick_load = match current {
+            Some(w) => {
+                let id = w.id;
+                ctx.link().callback(move |_| Msg::ReloadTriggered(id))
+            }
+            None => Callback::noop(),
+        };
+        let disabled = is_loading || current.is_none();
+        let can_sign = self.wallet().map(AppWallet::can_sign).unwrap_or(false);
+        let (disabled_sign_link, sign_title) = match self.wallet() {
+            Some(_) if !can_sign => (
+                Some("disabled"),
+                "This wallet is watch-only, sign the transaction with the wallet holding the keys",
+            ),
+            _ => (disabled_link, ""),
+        };
 
         html! {
             <div>
@@ -164,19 +766,26 @@ impl Component for App {
                     <header class="d-flex flex-wrap justify-content-center py-3 mb-4 border-bottom">
                         <a href="/" class="d-flex align-items-center mb-3 mb-md-0 me-md-auto text-dark text-decoration-none">
                             <span class="fs-4">{ "Elephant" }</span>
+                            if self.wallet().map(AppWallet::is_watch_only).unwrap_or(false) {
+                                <span class="badge bg-secondary ms-2">{ "watch-only" }</span>
+                            }
                         </a>
+                        { self.wallet_switcher(ctx) }
+                        { self.sync_status() }
                         <ul class="nav nav-pills">
-                        // TODO: active tab?
-                            <li class="nav-item"><a onclick={onclick(Tabs::KeyManagement)} class="nav-link" aria-current="page">{ "Wallet creator" }</a></li>
-                            <li class="nav-item"><a onclick={onclick(Tabs::Home)} class={classes!("nav-link", disabled_link)}>{ "Home" }</a></li>
-                            <li class="nav-item"><a onclick={onclick(Tabs::CreateTx)} class={classes!("nav-link", disabled_link)}>{ "Create transaction" }</a></li>
-                            <li class="nav-item"><a onclick={onclick(Tabs::SignTx)} class={classes!("nav-link", disabled_link)}>{ "Sign transaction" }</a></li>
-                            <li class="nav-item"><a onclick={onclick(Tabs::Merge)} class={classes!("nav-link", disabled_link)}>{ "Merge and broadcast" }</a></li>
-                            <button type="button" class="btn btn-primary" onclick={onclick_load} {disabled}>{if self.is_loading { "Loading..." } else { "Sync wallet" }}</button>
+                            <li class="nav-item"><a onclick={onclick(Tabs::KeyManagement)} class={classes!("nav-link", active(Tabs::KeyManagement))} aria-current={aria_current(Tabs::KeyManagement)}>{ "Wallet creator" }</a></li>
+                            <li class="nav-item"><a onclick={onclick(Tabs::Portfolio)} class={classes!("nav-link", active(Tabs::Portfolio), disabled_link)} aria-current={aria_current(Tabs::Portfolio)}>{ "Portfolio" }</a></li>
+                            <li class="nav-item"><a onclick={onclick(Tabs::Home)} class={classes!("nav-link", active(Tabs::Home), disabled_link)} aria-current={aria_current(Tabs::Home)}>{ "Home" }</a></li>
+                            <li class="nav-item"><a onclick={onclick(Tabs::CreateTx)} class={classes!("nav-link", active(Tabs::CreateTx), disabled_link)} aria-current={aria_current(Tabs::CreateTx)}>{ "Create transaction" }</a></li>
+                            <li class="nav-item" title={sign_title}><a onclick={onclick(Tabs::SignTx)} class={classes!("nav-link", active(Tabs::SignTx), disabled_sign_link)} aria-current={aria_current(Tabs::SignTx)}>{ "Sign transaction" }</a></li>
+                            <li class="nav-item"><a onclick={onclick(Tabs::Merge)} class={classes!("nav-link", active(Tabs::Merge), disabled_link)} aria-current={aria_current(Tabs::Merge)}>{ "Merge and broadcast" }</a></li>
+                            <button type="button" class="btn btn-primary" onclick={onclick_load} {disabled}>{if is_loading { "Syncing..." } else { "Sync wallet" }}</button>
                         </ul>
                     </header>
                 </div>
-                { self.create_tab() }
+                { self.tracked_notifications(ctx) }
+                { self.create_tab(ctx) }
+                { self.psbt_workspace(ctx) }
             </div>
         }
     }
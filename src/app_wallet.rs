@@ -1,45 +1,203 @@
 use std::{
-    cell::{Ref, RefCell, RefMut},
+    cell::{Cell, Ref, RefCell},
     rc::Rc,
 };
 
 use bdk::{
-    bitcoin::Network, blockchain::EsploraBlockchain, database::MemoryDatabase,
-    descriptor::IntoWalletDescriptor,
+    bitcoin::Network,
+    blockchain::EsploraBlockchain,
+    database::{Database, MemoryDatabase},
+    wallet::{AddressIndex, SyncOptions},
+    KeychainKind,
 };
+use serde::{Deserialize, Serialize};
 
 const BLOCKSTREAM_URL: &str = "https://blockstream.info/testnet/api";
 
+/// Backend used by the wallets of the current project
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct BackendSettings {
+    pub esplora_url: String,
+}
+
+impl Default for BackendSettings {
+    fn default() -> Self {
+        BackendSettings {
+            esplora_url: BLOCKSTREAM_URL.to_string(),
+        }
+    }
+}
+
+type BdkWallet = bdk::Wallet<MemoryDatabase>;
+/// Change to the wallet waiting for the sync to finish
+type Mutation = Box<dyn FnOnce(&mut BdkWallet)>;
+
+/// Handle on a wallet shared by every tab. The wallet stays readable while it syncs: the sync
+/// runs on a copy that replaces the wallet once it's done, and changes made in the meantime wait
+/// in a queue.
 #[derive(Clone)]
-pub struct AppWallet(pub Rc<RefCell<(bdk::Wallet<MemoryDatabase>, EsploraBlockchain)>>);
+pub struct AppWallet(Rc<Inner>);
+
+struct Inner {
+    wallet: RefCell<BdkWallet>,
+    blockchain: Rc<EsploraBlockchain>,
+    /// Descriptors with their private keys, to build the copy to sync
+    descriptor: String,
+    change_descriptor: Option<String>,
+    network: Network,
+    is_syncing: Cell<bool>,
+    queue: RefCell<Vec<Mutation>>,
+}
 
 impl AppWallet {
-    pub fn new<E: IntoWalletDescriptor>(
-        descriptor: E,
-        change_descriptor: Option<E>,
+    pub fn new(
+        descriptor: &str,
+        change_descriptor: Option<&str>,
         network: Network,
+        settings: &BackendSettings,
     ) -> Result<Self, bdk::Error> {
-        let wallet = bdk::Wallet::new(
-            descriptor,
-            change_descriptor,
+        let wallet = build(descriptor, change_descriptor, network)?;
+        let esplora = EsploraBlockchain::new(&settings.esplora_url, 20);
+        Ok(Self(Rc::new(Inner {
+            wallet: RefCell::new(wallet),
+            blockchain: Rc::new(esplora),
+            descriptor: descriptor.to_string(),
+            change_descriptor: change_descriptor.map(str::to_string),
             network,
-            MemoryDatabase::new(),
-        )?;
-        let esplora = EsploraBlockchain::new(BLOCKSTREAM_URL, 20);
-        Ok(Self(Rc::new(RefCell::new((wallet, esplora)))))
+            is_syncing: Cell::new(false),
+            queue: RefCell::new(vec![]),
+        })))
     }
 
-    pub fn borrow(&self) -> Ref<(bdk::Wallet<MemoryDatabase>, EsploraBlockchain)> {
-        self.0.borrow()
+    /// Reads the wallet, never hold it across an `await`
+    pub fn borrow(&self) -> Ref<'_, BdkWallet> {
+        self.0.wallet.borrow()
     }
 
-    pub fn borrow_mut(&self) -> RefMut<(bdk::Wallet<MemoryDatabase>, EsploraBlockchain)> {
-        self.0.borrow_mut()
+    /// Backend of the wallet, to use in futures instead of borrowing the wallet
+    pub fn blockchain(&self) -> Rc<EsploraBlockchain> {
+        self.0.blockchain.clone()
+    }
+
+    pub fn is_syncing(&self) -> bool {
+        self.0.is_syncing.get()
+    }
+
+    /// Changes the wallet now, or once the sync finishes if it's syncing
+    pub fn mutate(&self, mutation: impl FnOnce(&mut BdkWallet) + 'static) {
+        if self.is_syncing() {
+            self.0.queue.borrow_mut().push(Box::new(mutation));
+        } else {
+            mutation(&mut self.0.wallet.borrow_mut());
+        }
+    }
+
+    /// Syncs a copy of the wallet with the backend, then replaces the wallet with it and applies
+    /// the queued changes
+    pub async fn sync(&self) -> Result<(), String> {
+        if self.is_syncing() {
+            return Err("The wallet is already syncing".to_string());
+        }
+        let synced = build(
+            &self.0.descriptor,
+            self.0.change_descriptor.as_deref(),
+            self.0.network,
+        )
+        .map_err(|e| e.to_string())?;
+
+        self.0.is_syncing.set(true);
+        let res = synced
+            .sync(&*self.0.blockchain, SyncOptions::default())
+            .await
+            .map_err(|e| e.to_string());
+        if res.is_ok() {
+            let mut wallet = self.0.wallet.borrow_mut();
+            keep_last_index(&wallet, &synced, KeychainKind::External);
+            if self.0.change_descriptor.is_some() {
+                keep_last_index(&wallet, &synced, KeychainKind::Internal);
+            }
+            *wallet = synced;
+        }
+        self.0.is_syncing.set(false);
+
+        let queue = self.0.queue.take();
+        for mutation in queue {
+            mutation(&mut self.0.wallet.borrow_mut());
+        }
+
+        res
+    }
+
+    /// Height of the tip at the last sync
+    pub fn synced_height(&self) -> Option<u32> {
+        self.borrow()
+            .database()
+            .get_sync_time()
+            .ok()
+            .flatten()
+            .map(|t| t.block_time.height)
+    }
+
+    /// A wallet is watch-only when we don't know any of its private keys
+    pub fn is_watch_only(&self) -> bool {
+        self.borrow()
+            .get_signers(KeychainKind::External)
+            .signers()
+            .is_empty()
+    }
+
+    /// Whether the wallet has hash-lock branches, that can be satisfied without private keys
+    pub fn uses_hash_locks(&self) -> bool {
+        let descriptor = self
+            .borrow()
+            .public_descriptor(KeychainKind::External)
+            .ok()
+            .flatten()
+            .map(|d| d.to_string())
+            .unwrap_or_default();
+
+        ["sha256(", "hash256(", "ripemd160(", "hash160("]
+            .iter()
+            .any(|hash| descriptor.contains(hash))
+    }
+
+    /// Watch-only wallets can't sign, unless there's a preimage to add
+    pub fn can_sign(&self) -> bool {
+        !self.is_watch_only() || self.uses_hash_locks()
     }
 }
 
 impl PartialEq for AppWallet {
     fn eq(&self, other: &Self) -> bool {
-        self.0.as_ptr() == other.0.as_ptr()
+        Rc::ptr_eq(&self.0, &other.0)
+    }
+}
+
+fn build(
+    descriptor: &str,
+    change_descriptor: Option<&str>,
+    network: Network,
+) -> Result<BdkWallet, bdk::Error> {
+    bdk::Wallet::new(
+        descriptor,
+        change_descriptor,
+        network,
+        MemoryDatabase::new(),
+    )
+}
+
+/// The synced copy only knows the addresses used on chain, keep the ones handed out since
+fn keep_last_index(old: &BdkWallet, synced: &BdkWallet, keychain: KeychainKind) {
+    let last_index = |w: &BdkWallet| w.database().get_last_index(keychain).ok().flatten();
+    let index = match last_index(old) {
+        Some(index) if !matches!(last_index(synced), Some(i) if i >= index) => index,
+        _ => return,
+    };
+    let res = match keychain {
+        KeychainKind::External => synced.get_address(AddressIndex::Reset(index)),
+        KeychainKind::Internal => synced.get_internal_address(AddressIndex::Reset(index)),
+    };
+    if let Err(e) = res {
+        log::warn!("Failed to keep the address index: {}", e);
     }
 }
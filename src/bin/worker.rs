@@ -0,0 +1,7 @@
+use elephant::compiler::CompileWorker;
+use yew_agent::Threaded;
+
+fn main() {
+    wasm_logger::init(wasm_logger::Config::default());
+    CompileWorker::register();
+}
@@ -0,0 +1,186 @@
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use bdk::bitcoin::VarInt;
+use bdk::miniscript::policy::Concrete;
+use bdk::miniscript::{Descriptor, Miniscript, Tap};
+use gloo_timers::callback::Timeout;
+use serde::{Deserialize, Serialize};
+use yew_agent::{Agent, AgentLink, HandlerId, Public};
+
+use crate::app::UNSPENDABLE_KEY;
+use crate::wallet_export;
+use crate::wallets::WalletDescriptors;
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompileRequest {
+    /// Increasing id, to recognize the responses to stale requests
+    pub id: u64,
+    pub policy: String,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+pub struct CompileResponse {
+    pub id: u64,
+    pub policy: String,
+    pub result: Result<Compiled, String>,
+}
+
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct Compiled {
+    pub descriptors: WalletDescriptors,
+    pub weights: SatisfactionWeights,
+}
+
+/// Largest witness weight needed to spend with each script of a taproot descriptor, in weight
+/// units
+#[derive(Clone, Debug, Default, PartialEq, Serialize, Deserialize)]
+pub struct SatisfactionWeights {
+    /// Weight of the most expensive way to spend
+    pub max: Option<usize>,
+    /// Miniscript of each leaf with its weight, cheapest first
+    pub leaves: Vec<(String, usize)>,
+}
+
+/// Compilation of the policy designed in the wallet creator, as shown to the user
+#[derive(Clone, Debug, Default, PartialEq)]
+pub enum Compilation {
+    #[default]
+    Idle,
+    Running,
+    Done(Result<SatisfactionWeights, String>),
+}
+
+/// Compiles a policy into a taproot descriptor, descriptors are used as they are. Keys with a
+/// receiving path get a change descriptor too.
+pub fn compile_policy(policy: &str) -> Result<Compiled, String> {
+    let external = match Concrete::<String>::from_str(policy) {
+        Ok(policy) => {
+            let policy: Miniscript<String, Tap> = policy.compile().map_err(|e| e.to_string())?;
+            format!("tr({},{})", UNSPENDABLE_KEY, policy)
+        }
+        Err(_) => policy.to_string(),
+    };
+    let internal = wallet_export::change_descriptor(&external);
+    let weights = satisfaction_weights(&external);
+
+    Ok(Compiled {
+        descriptors: WalletDescriptors { external, internal },
+        weights,
+    })
+}
+
+fn satisfaction_weights(descriptor: &str) -> SatisfactionWeights {
+    let descriptor = match Descriptor::<String>::from_str(descriptor) {
+        Ok(descriptor) => descriptor,
+        Err(_) => return SatisfactionWeights::default(),
+    };
+    let mut leaves = match &descriptor {
+        Descriptor::Tr(tr) => tr
+            .iter_scripts()
+            .filter_map(|(depth, ms)| Some((ms.to_string(), leaf_weight(depth, ms)?)))
+            .collect(),
+        _ => vec![],
+    };
+    leaves.sort_by_key(|(_, weight)| *weight);
+
+    SatisfactionWeights {
+        max: descriptor.max_satisfaction_weight().ok(),
+        leaves,
+    }
+}
+
+/// Same as `Tr::max_satisfaction_weight`, for a single leaf at `depth` in the tree
+fn leaf_weight(depth: u8, ms: &Miniscript<String, Tap>) -> Option<usize> {
+    let varint_len = |n: usize| VarInt(n as u64).len();
+    let script_size = ms.script_size();
+    let control_block_size = 33 + 32 * depth as usize;
+
+    Some(
+        4 + varint_len(ms.max_satisfaction_witness_elements().ok()? + 2)
+            + ms.max_satisfaction_size().ok()?
+            + varint_len(script_size)
+            + script_size
+            + varint_len(control_block_size)
+            + control_block_size,
+    )
+}
+
+pub enum CompileWorkerMsg {
+    Run,
+}
+
+/// Compiles policies in a web worker, so that large policies don't freeze Blockly.
+///
+/// Requests arriving while a policy is being compiled wait in the queue: only the latest request
+/// of each bridge is compiled, the ones it replaces are dropped without an answer. A running
+/// compilation can't be cancelled: yew-agent doesn't terminate a busy worker.
+pub struct CompileWorker {
+    link: AgentLink<Self>,
+    pending: HashMap<HandlerId, CompileRequest>,
+    scheduled: Option<Timeout>,
+}
+
+impl CompileWorker {
+    /// Compiles on the next turn of the event loop, after the requests already posted
+    fn schedule(&mut self) {
+        if self.scheduled.is_none() && !self.pending.is_empty() {
+            let link = self.link.clone();
+            self.scheduled = Some(Timeout::new(0, move || {
+                link.send_message(CompileWorkerMsg::Run)
+            }));
+        }
+    }
+}
+
+impl Agent for CompileWorker {
+    type Reach = Public<Self>;
+    type Message = CompileWorkerMsg;
+    type Input = CompileRequest;
+    type Output = CompileResponse;
+
+    fn create(link: AgentLink<Self>) -> Self {
+        CompileWorker {
+            link,
+            pending: HashMap::new(),
+            scheduled: None,
+        }
+    }
+
+    fn update(&mut self, msg: Self::Message) {
+        match msg {
+            CompileWorkerMsg::Run => {
+                self.scheduled = None;
+                let who = match self.pending.keys().next() {
+                    Some(who) => *who,
+                    None => return,
+                };
+                let request = self.pending.remove(&who).unwrap();
+                let result = compile_policy(&request.policy);
+                self.link.respond(
+                    who,
+                    CompileResponse {
+                        id: request.id,
+                        policy: request.policy,
+                        result,
+                    },
+                );
+
+                self.schedule();
+            }
+        }
+    }
+
+    fn handle_input(&mut self, request: Self::Input, who: HandlerId) {
+        self.pending.insert(who, request);
+        self.schedule();
+    }
+
+    fn disconnected(&mut self, who: HandlerId) {
+        self.pending.remove(&who);
+    }
+
+    fn name_of_resource() -> &'static str {
+        "worker.js"
+    }
+}
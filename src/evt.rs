@@ -1,43 +1,98 @@
-use serde::{Deserialize, Serialize};
+use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
+use bdk::bitcoin::Txid;
 use std::collections::HashSet;
 use yew_agent::{Agent, AgentLink, Context, HandlerId};
 
-#[derive(Serialize, Deserialize, Debug)]
-pub enum Request {
-    EventBusMsg(String),
+use crate::app_wallet::BackendSettings;
+
+/// Everything that happens in the app and other components may care about. Subscribers receive
+/// every event, and pick the ones they need.
+#[derive(Clone, Debug)]
+pub enum AppEvent {
+    /// New policy or descriptor from the wallet creator, empty when there's no valid wallet
+    PolicyChanged(String),
+    /// Backend settings of the project open in the wallet creator. Wallets are built again when
+    /// they change.
+    SettingsChanged(BackendSettings),
+    /// The keys of the wallet creator have been locked, their private keys must be forgotten
+    Locked,
+    /// The current wallet changed, with its id
+    WalletLoaded(Option<usize>),
+    SyncStarted(usize),
+    SyncFinished(usize),
+
+    PsbtCreated(PartiallySignedTransaction),
+    PsbtSigned(PartiallySignedTransaction),
+    PsbtMerged(PartiallySignedTransaction),
+    Broadcast(Result<Txid, String>),
 }
 
+/// Broadcasts the events to every bridge. Bridges connecting later first receive the current
+/// state: the current wallet and the wallets being synced.
 pub struct EventBus {
     link: AgentLink<EventBus>,
     subscribers: HashSet<HandlerId>,
+    wallet: Option<usize>,
+    syncing: HashSet<usize>,
+}
+
+impl EventBus {
+    fn state(&self) -> Vec<AppEvent> {
+        let mut events = vec![AppEvent::WalletLoaded(self.wallet)];
+        events.extend(self.syncing.iter().map(|id| AppEvent::SyncStarted(*id)));
+
+        events
+    }
 }
 
 impl Agent for EventBus {
     type Reach = Context<Self>;
     type Message = ();
-    type Input = Request;
-    type Output = String;
+    type Input = AppEvent;
+    type Output = AppEvent;
 
     fn create(link: AgentLink<Self>) -> Self {
         Self {
             link,
             subscribers: HashSet::new(),
+            wallet: None,
+            syncing: HashSet::new(),
         }
     }
 
     fn update(&mut self, _msg: Self::Message) {}
 
-    fn handle_input(&mut self, msg: Self::Input, _id: HandlerId) {
-        match msg {
-            Request::EventBusMsg(s) => {
-                for sub in self.subscribers.iter() {
-                    self.link.respond(*sub, s.clone());
-                }
+    fn handle_input(&mut self, event: Self::Input, _id: HandlerId) {
+        match &event {
+            AppEvent::WalletLoaded(id) => self.wallet = *id,
+            AppEvent::SyncStarted(id) => {
+                self.syncing.insert(*id);
+            }
+            AppEvent::SyncFinished(id) => {
+                self.syncing.remove(id);
             }
+            AppEvent::PsbtCreated(psbt)
+            | AppEvent::PsbtSigned(psbt)
+            | AppEvent::PsbtMerged(psbt) => {
+                log::info!("PSBT updated: {}", psbt.unsigned_tx.txid());
+            }
+            _ => {}
+        }
+
+        for sub in self.subscribers.iter() {
+            self.link.respond(*sub, event.clone());
         }
     }
 
     fn connected(&mut self, id: HandlerId) {
+        // Dispatchers only send events
+        if !id.is_respondable() {
+            return;
+        }
+
+        for event in self.state() {
+            self.link.respond(id, event);
+        }
         self.subscribers.insert(id);
     }
 
@@ -0,0 +1,139 @@
+use bdk::bitcoin::hashes::hex::{FromHex, ToHex};
+use bdk::bitcoin::hashes::{hash160, ripemd160, sha256, sha256d, Hash};
+use bdk::bitcoin::psbt::Psbt;
+use bdk::bitcoin::Script;
+
+/// Hash functions available in hash-lock leaves
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum HashKind {
+    Sha256,
+    Hash256,
+    Ripemd160,
+    Hash160,
+}
+
+impl HashKind {
+    pub fn all() -> [HashKind; 4] {
+        [
+            HashKind::Sha256,
+            HashKind::Hash256,
+            HashKind::Ripemd160,
+            HashKind::Hash160,
+        ]
+    }
+
+    /// Name of the Blockly block and of the policy fragment
+    pub fn name(&self) -> &'static str {
+        match self {
+            HashKind::Sha256 => "sha256",
+            HashKind::Hash256 => "hash256",
+            HashKind::Ripemd160 => "ripemd160",
+            HashKind::Hash160 => "hash160",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        HashKind::all().into_iter().find(|k| k.name() == name)
+    }
+
+    /// Length of the digest in bytes
+    pub fn digest_len(&self) -> usize {
+        match self {
+            HashKind::Sha256 | HashKind::Hash256 => 32,
+            HashKind::Ripemd160 | HashKind::Hash160 => 20,
+        }
+    }
+
+    /// Digest of `data`, in the byte order used in scripts
+    pub fn hash(&self, data: &[u8]) -> Vec<u8> {
+        match self {
+            HashKind::Sha256 => sha256::Hash::hash(data).to_vec(),
+            HashKind::Hash256 => sha256d::Hash::hash(data).to_vec(),
+            HashKind::Ripemd160 => ripemd160::Hash::hash(data).to_vec(),
+            HashKind::Hash160 => hash160::Hash::hash(data).to_vec(),
+        }
+    }
+
+    /// Validates a hex-encoded digest
+    pub fn parse_digest(&self, hex: &str) -> Option<Vec<u8>> {
+        Vec::<u8>::from_hex(hex.trim())
+            .ok()
+            .filter(|d| d.len() == self.digest_len())
+    }
+
+    /// Hex-encoded digest of the preimage derived from `secret`
+    pub fn digest_of_secret(&self, secret: &str) -> String {
+        self.hash(&preimage_from_secret(secret)).to_hex()
+    }
+}
+
+/// Miniscript only accepts 32-byte preimages: 64 hex characters are used as they are, anything
+/// else is hashed with SHA256 to obtain one.
+pub fn preimage_from_secret(secret: &str) -> [u8; 32] {
+    let mut preimage = [0u8; 32];
+    match Vec::<u8>::from_hex(secret.trim()) {
+        Ok(bytes) if bytes.len() == 32 => preimage.copy_from_slice(&bytes),
+        _ => preimage.copy_from_slice(&sha256::Hash::hash(secret.as_bytes())[..]),
+    }
+
+    preimage
+}
+
+/// Adds the preimage derived from `secret` to every input whose scripts contain one of its
+/// digests. Returns the number of preimages added.
+pub fn add_preimage(psbt: &mut Psbt, secret: &str) -> usize {
+    let preimage = preimage_from_secret(secret).to_vec();
+    let mut added = 0;
+
+    for input in psbt.inputs.iter_mut() {
+        let scripts = input
+            .tap_scripts
+            .values()
+            .map(|(script, _)| script)
+            .chain(input.witness_script.as_ref())
+            .collect::<Vec<_>>();
+
+        for kind in HashKind::all() {
+            let digest = kind.hash(&preimage);
+            if !scripts.iter().any(|s| contains(s, &digest)) {
+                continue;
+            }
+
+            match kind {
+                HashKind::Sha256 => {
+                    input
+                        .sha256_preimages
+                        .insert(sha256::Hash::from_slice(&digest).unwrap(), preimage.clone());
+                }
+                HashKind::Hash256 => {
+                    input.hash256_preimages.insert(
+                        sha256d::Hash::from_slice(&digest).unwrap(),
+                        preimage.clone(),
+                    );
+                }
+                HashKind::Ripemd160 => {
+                    input.ripemd160_preimages.insert(
+                        ripemd160::Hash::from_slice(&digest).unwrap(),
+                        preimage.clone(),
+                    );
+                }
+                HashKind::Hash160 => {
+                    input.hash160_preimages.insert(
+                        hash160::Hash::from_slice(&digest).unwrap(),
+                        preimage.clone(),
+                    );
+                }
+            }
+            added += 1;
+        }
+    }
+
+    added
+}
+
+fn contains(script: &Script, digest: &[u8]) -> bool {
+    script
+        .as_bytes()
+        .windows(digest.len())
+        .any(|window| window == digest)
+}
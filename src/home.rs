@@ -1,5 +1,8 @@
+use crate::qr::{bbqr_encode, bip21_uri, BbqrType, QrView};
+use crate::wallet_export::WalletExportView;
 use crate::AppWallet;
 use bdk::wallet::AddressIndex;
+use bdk::KeychainKind;
 use yew::prelude::*;
 
 pub enum Msg {
@@ -15,10 +18,23 @@ pub struct Props {
 pub struct Home {
     props: Props,
     address: String,
+    descriptor: String,
 }
 
 impl Home {}
 
+/// Formats an amount in sats as `B.BB SSS SSS`
+pub fn satcommify(mut n: u64) -> String {
+    let sats_3 = n % 1000;
+    n /= 1000;
+    let sats_2 = n % 1000;
+    n /= 1000;
+    let sats_1 = n % 100;
+    n /= 100;
+    let btc = n;
+    format!("{}.{:02} {:03} {:03}", btc, sats_1, sats_2, sats_3)
+}
+
 impl Component for Home {
     type Message = Msg;
     type Properties = Props;
@@ -28,27 +44,25 @@ impl Component for Home {
         let address = props
             .wallet
             .borrow()
-            .0
             .get_address(AddressIndex::New)
             .unwrap()
             .to_string();
+        let descriptor = props
+            .wallet
+            .borrow()
+            .public_descriptor(KeychainKind::External)
+            .ok()
+            .flatten()
+            .map(|d| d.to_string())
+            .unwrap_or_default();
         Self {
             props,
             address,
+            descriptor,
         }
     }
 
     fn view(&self, _ctx: &Context<Self>) -> Html {
-        let satcommify = |mut n: u64| {
-            let sats_3 = n % 1000;
-            n /= 1000;
-            let sats_2 = n % 1000;
-            n /= 1000;
-            let sats_1 = n % 100;
-            n /= 100;
-            let btc = n;
-            format!("{}.{:02} {:03} {:03}", btc, sats_1, sats_2, sats_3)
-        };
         html! {
             <div>
                 <div class="daniela-home text-center">
@@ -57,7 +71,10 @@ impl Component for Home {
                         <div class="balance-unconfirmed"> { format!("+ {} sats unconfirmed", satcommify(self.props.balance.untrusted_pending)) } </div>
                     </div>
                     <div class="address"> { format!("Receiving address: {}", self.address) } </div>
+                    <QrView frames={vec![bip21_uri(&self.address)]} shown=true/>
+                    <QrView frames={bbqr_encode(self.descriptor.as_bytes(), BbqrType::UnicodeText)} label={"descriptor QR code".to_string()}/>
                 </div>
+                <WalletExportView wallet={self.props.wallet.clone()} />
                 <div class="table-responsive">
                     <table class="table-sm daniela-table">
                         <thead>
@@ -1,4 +1,6 @@
-use serde::Serialize;
+use std::collections::HashMap;
+
+use serde::{Deserialize, Serialize};
 use wasm_bindgen::{prelude::*, JsValue};
 
 #[wasm_bindgen]
@@ -8,8 +10,11 @@ extern "C" {
     #[wasm_bindgen(js_namespace = Blockly)]
     fn defineBlocksWithJsonArray(val: &JsValue);
 
-    #[wasm_bindgen(js_namespace = BlocklyStorage)]
-    fn backupOnUnload();
+    #[wasm_bindgen(js_namespace = ["Blockly", "serialization", "workspaces"], js_name = save)]
+    fn serializeWorkspace(workspace: &JsValue) -> JsValue;
+    #[wasm_bindgen(js_namespace = ["Blockly", "serialization", "workspaces"], js_name = load)]
+    fn deserializeWorkspace(state: &JsValue, workspace: &JsValue);
+
     #[wasm_bindgen(js_namespace = BlocklyStorage)]
     fn restoreBlocks();
 
@@ -17,8 +22,6 @@ extern "C" {
     fn initJs(workspace: &JsValue, compiled_cb: &JsValue, dropdown_cb: &JsValue);
     #[wasm_bindgen(js_namespace = BlocklyExt)]
     fn insertBegin(workspace: &JsValue);
-    #[wasm_bindgen(js_namespace = BlocklyExt)]
-    fn saveBlockly(workspace: &JsValue);
 }
 
 pub struct Workspace(JsValue);
@@ -35,16 +38,13 @@ pub fn inject_blockly(id: &str, opts: &BlocklyOptions) -> Workspace {
     Workspace(inject(id, &jsval))
 }
 
-pub fn enable_backup() {
-    backupOnUnload();
-}
 pub fn restore_blocks() {
     restoreBlocks();
 }
 
 pub fn init_js(
     workspace: &Workspace,
-    compiled_cb: &Closure<dyn FnMut(String)>,
+    compiled_cb: &Closure<dyn FnMut(JsValue)>,
     dropdown_cb: &Closure<dyn FnMut() -> JsValue>,
 ) {
     initJs(&workspace.0, compiled_cb.as_ref(), dropdown_cb.as_ref());
@@ -52,8 +52,14 @@ pub fn init_js(
 pub fn insert_begin(workspace: &Workspace) {
     insertBegin(&workspace.0);
 }
-pub fn save_blockly(workspace: &Workspace) {
-    saveBlockly(&workspace.0)
+pub fn export_workspace(workspace: &Workspace) -> serde_json::Value {
+    serde_wasm_bindgen::from_value(serializeWorkspace(&workspace.0)).unwrap()
+}
+pub fn import_workspace(workspace: &Workspace, state: &serde_json::Value) {
+    let jsval = state
+        .serialize(&serde_wasm_bindgen::Serializer::json_compatible())
+        .unwrap();
+    deserializeWorkspace(&jsval, &workspace.0);
 }
 
 #[derive(Debug, Serialize)]
@@ -120,6 +126,10 @@ pub struct BlocklyBlockArg {
     pub check: Option<ValueType>,
     #[serde(skip_serializing_if = "Option::is_none")]
     pub value: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub text: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub options: Option<Vec<(&'static str, &'static str)>>,
 }
 
 impl BlocklyBlockArg {
@@ -129,6 +139,8 @@ impl BlocklyBlockArg {
             check: Some(check),
             ty: "input_statement",
             value: None,
+            text: None,
+            options: None,
         }
     }
     pub fn input_value(name: &'static str, check: ValueType) -> Self {
@@ -137,6 +149,8 @@ impl BlocklyBlockArg {
             check: Some(check),
             ty: "input_value",
             value: None,
+            text: None,
+            options: None,
         }
     }
     pub fn dummy() -> Self {
@@ -145,6 +159,8 @@ impl BlocklyBlockArg {
             check: None,
             ty: "input_dummy",
             value: None,
+            text: None,
+            options: None,
         }
     }
     pub fn field_number(name: &'static str, value: &'static str) -> Self {
@@ -153,6 +169,28 @@ impl BlocklyBlockArg {
             check: None,
             ty: "field_number",
             value: Some(value),
+            text: None,
+            options: None,
+        }
+    }
+    pub fn field_input(name: &'static str, text: &'static str) -> Self {
+        BlocklyBlockArg {
+            name: Some(name),
+            check: None,
+            ty: "field_input",
+            value: None,
+            text: Some(text),
+            options: None,
+        }
+    }
+    pub fn field_dropdown(name: &'static str, options: Vec<(&'static str, &'static str)>) -> Self {
+        BlocklyBlockArg {
+            name: Some(name),
+            check: None,
+            ty: "field_dropdown",
+            value: None,
+            text: None,
+            options: Some(options),
         }
     }
 }
@@ -174,3 +212,58 @@ pub struct BlocklyBlock {
     pub colour: u16,
     pub extensions: Vec<&'static str>,
 }
+
+/// Workspace as produced by `Blockly.serialization.workspaces.save`
+#[derive(Debug, Default, PartialEq, Deserialize)]
+pub struct SerializedWorkspace {
+    #[serde(default)]
+    pub blocks: SerializedBlocks,
+}
+
+#[derive(Debug, Default, PartialEq, Deserialize)]
+pub struct SerializedBlocks {
+    /// Top-level blocks
+    #[serde(default)]
+    pub blocks: Vec<SerializedBlock>,
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct SerializedBlock {
+    #[serde(rename = "type")]
+    pub ty: String,
+    #[serde(default)]
+    pub fields: HashMap<String, serde_json::Value>,
+    #[serde(default)]
+    pub inputs: HashMap<String, SerializedConnection>,
+    pub next: Option<SerializedConnection>,
+}
+
+impl SerializedBlock {
+    /// Block connected to the given input, if any
+    pub fn input(&self, name: &str) -> Option<&SerializedBlock> {
+        self.inputs.get(name).and_then(|c| c.block.as_deref())
+    }
+
+    /// Block connected to the next statement, if any
+    pub fn next(&self) -> Option<&SerializedBlock> {
+        self.next.as_ref().and_then(|c| c.block.as_deref())
+    }
+
+    pub fn field_str(&self, name: &str) -> Option<&str> {
+        self.fields.get(name).and_then(|v| v.as_str())
+    }
+
+    /// Number fields are serialized as numbers, but older workspaces may contain strings
+    pub fn field_u32(&self, name: &str) -> Option<u32> {
+        match self.fields.get(name)? {
+            serde_json::Value::Number(n) => n.as_u64().and_then(|n| n.try_into().ok()),
+            serde_json::Value::String(s) => s.parse().ok(),
+            _ => None,
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Deserialize)]
+pub struct SerializedConnection {
+    pub block: Option<Box<SerializedBlock>>,
+}
@@ -0,0 +1,225 @@
+use std::cell::RefCell;
+
+use chacha20poly1305::aead::{Aead, KeyInit};
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce};
+use hmac::Hmac;
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+/// PBKDF2-HMAC-SHA256 iterations, a compromise between security and unlock time in wasm
+pub const PBKDF2_ROUNDS: u32 = 100_000;
+/// The keys are locked after this many milliseconds without interactions
+pub const AUTO_LOCK_MS: f64 = 10.0 * 60.0 * 1000.0;
+
+const SALT_LEN: usize = 16;
+const NONCE_LEN: usize = 12;
+const CORRUPTED: &str = "Corrupted encrypted data";
+
+/// Data encrypted with ChaCha20-Poly1305, using a key derived from a passphrase
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Encrypted {
+    pub rounds: u32,
+    /// Base64-encoded
+    pub salt: String,
+    pub nonce: String,
+    pub ciphertext: String,
+}
+
+/// Key derived from the passphrase. It's kept in memory while the key manager is unlocked, so
+/// that saving doesn't require running the KDF again.
+#[derive(Clone)]
+pub struct EncryptionKey {
+    key: [u8; 32],
+    salt: [u8; SALT_LEN],
+    rounds: u32,
+}
+
+impl EncryptionKey {
+    /// Derives a key from a new passphrase, with a random salt
+    pub fn new(passphrase: &str) -> Self {
+        let mut salt = [0u8; SALT_LEN];
+        getrandom::getrandom(&mut salt).expect("random salt");
+
+        Self::derive(passphrase, salt, PBKDF2_ROUNDS)
+    }
+
+    fn derive(passphrase: &str, salt: [u8; SALT_LEN], rounds: u32) -> Self {
+        let mut key = [0u8; 32];
+        pbkdf2::pbkdf2::<Hmac<Sha256>>(passphrase.as_bytes(), &salt, rounds, &mut key);
+
+        EncryptionKey { key, salt, rounds }
+    }
+
+    /// Derives the key from `passphrase` and decrypts `encrypted` with it. The key is returned to
+    /// encrypt the following saves.
+    pub fn unlock(passphrase: &str, encrypted: &Encrypted) -> Result<(Self, Vec<u8>), String> {
+        let salt = decode(&encrypted.salt)?
+            .try_into()
+            .map_err(|_| CORRUPTED.to_string())?;
+        let key = Self::derive(passphrase, salt, encrypted.rounds);
+        let plaintext = key.decrypt(encrypted)?;
+
+        Ok((key, plaintext))
+    }
+
+    pub fn decrypt(&self, encrypted: &Encrypted) -> Result<Vec<u8>, String> {
+        let nonce = decode(&encrypted.nonce)?;
+        let ciphertext = decode(&encrypted.ciphertext)?;
+        if nonce.len() != NONCE_LEN {
+            return Err(CORRUPTED.to_string());
+        }
+
+        self.cipher()
+            .decrypt(Nonce::from_slice(&nonce), ciphertext.as_slice())
+            .map_err(|_| "Wrong passphrase".to_string())
+    }
+
+    pub fn encrypt(&self, plaintext: &[u8]) -> Encrypted {
+        let mut nonce = [0u8; NONCE_LEN];
+        getrandom::getrandom(&mut nonce).expect("random nonce");
+        let ciphertext = self
+            .cipher()
+            .encrypt(Nonce::from_slice(&nonce), plaintext)
+            .expect("encryption doesn't fail with a valid key");
+
+        Encrypted {
+            rounds: self.rounds,
+            salt: base64::encode(self.salt),
+            nonce: base64::encode(nonce),
+            ciphertext: base64::encode(ciphertext),
+        }
+    }
+
+    fn cipher(&self) -> ChaCha20Poly1305 {
+        ChaCha20Poly1305::new(Key::from_slice(&self.key))
+    }
+}
+
+fn decode(data: &str) -> Result<Vec<u8>, String> {
+    base64::decode(data).map_err(|_| CORRUPTED.to_string())
+}
+
+struct Session {
+    key: EncryptionKey,
+    last_activity: f64,
+}
+
+impl Session {
+    fn is_expired(&self) -> bool {
+        js_sys::Date::now() - self.last_activity > AUTO_LOCK_MS
+    }
+}
+
+thread_local! {
+    /// Unlocked key, shared between instances of the key manager so that switching tab doesn't
+    /// ask for the passphrase again
+    static SESSION: RefCell<Option<Session>> = const { RefCell::new(None) };
+}
+
+pub fn start_session(key: EncryptionKey) {
+    SESSION.with(|s| {
+        *s.borrow_mut() = Some(Session {
+            key,
+            last_activity: js_sys::Date::now(),
+        })
+    });
+}
+
+pub fn end_session() {
+    SESSION.with(|s| *s.borrow_mut() = None);
+}
+
+/// Records an interaction, postponing the auto-lock
+pub fn touch_session() {
+    SESSION.with(|s| {
+        if let Some(session) = s.borrow_mut().as_mut() {
+            session.last_activity = js_sys::Date::now();
+        }
+    });
+}
+
+/// Returns the unlocked key, unless the session has expired
+pub fn session_key() -> Option<EncryptionKey> {
+    SESSION.with(|s| {
+        s.borrow()
+            .as_ref()
+            .filter(|s| !s.is_expired())
+            .map(|s| s.key.clone())
+    })
+}
+
+/// Ends the session if it has expired, returning whether it did
+pub fn expire_session() -> bool {
+    SESSION.with(|s| {
+        let mut session = s.borrow_mut();
+        let expired = session.as_ref().is_some_and(Session::is_expired);
+        if expired {
+            *session = None;
+        }
+
+        expired
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trip() {
+        let key = EncryptionKey::new("correct horse");
+        let encrypted = key.encrypt(b"my keys");
+        assert_eq!(encrypted.rounds, PBKDF2_ROUNDS);
+        assert_eq!(key.decrypt(&encrypted).unwrap(), b"my keys");
+
+        let (unlocked, plaintext) = EncryptionKey::unlock("correct horse", &encrypted).unwrap();
+        assert_eq!(plaintext, b"my keys");
+        assert_eq!(unlocked.key, key.key);
+    }
+
+    #[test]
+    fn fresh_nonce_and_salt() {
+        let key = EncryptionKey::derive("passphrase", [1; SALT_LEN], 10);
+        let (a, b) = (key.encrypt(b"data"), key.encrypt(b"data"));
+        assert_ne!(a.nonce, b.nonce);
+        assert_ne!(a.ciphertext, b.ciphertext);
+
+        let other = EncryptionKey::derive("passphrase", [2; SALT_LEN], 10);
+        assert_ne!(key.key, other.key);
+    }
+
+    #[test]
+    fn wrong_passphrase() {
+        let key = EncryptionKey::derive("passphrase", [7; SALT_LEN], 10);
+        let encrypted = key.encrypt(b"my keys");
+
+        assert_eq!(
+            EncryptionKey::unlock("Passphrase", &encrypted).err(),
+            Some("Wrong passphrase".to_string())
+        );
+        assert!(EncryptionKey::unlock("passphrase", &encrypted).is_ok());
+    }
+
+    #[test]
+    fn corrupted_data() {
+        let key = EncryptionKey::derive("passphrase", [7; SALT_LEN], 10);
+        let encrypted = key.encrypt(b"my keys");
+
+        let mut tampered = encrypted.clone();
+        let mut ciphertext = base64::decode(&tampered.ciphertext).unwrap();
+        ciphertext[0] ^= 1;
+        tampered.ciphertext = base64::encode(ciphertext);
+        assert!(key.decrypt(&tampered).is_err());
+
+        let mut bad_nonce = encrypted.clone();
+        bad_nonce.nonce = base64::encode([0u8; 4]);
+        assert_eq!(key.decrypt(&bad_nonce).unwrap_err(), CORRUPTED);
+
+        let mut bad_salt = encrypted;
+        bad_salt.salt = "not base64!".to_string();
+        assert_eq!(
+            EncryptionKey::unlock("passphrase", &bad_salt).err(),
+            Some(CORRUPTED.to_string())
+        );
+    }
+}
@@ -0,0 +1,93 @@
+use std::str::FromStr;
+
+use bdk::bitcoin::secp256k1::Secp256k1;
+use bdk::bitcoin::PrivateKey;
+use bdk::miniscript::descriptor::{DescriptorPublicKey, DescriptorSecretKey};
+use bdk::miniscript::policy::Concrete;
+use bdk::miniscript::{Descriptor, Tap, TranslatePk, Translator};
+
+use super::keys::{to_public, with_origin};
+use super::State;
+use crate::wallet_export;
+
+/// Replaces aliases known to the key manager with the corresponding keys. Secret keys are
+/// replaced by public keys when importing a watch-only wallet.
+struct AliasResolver<'s> {
+    state: &'s State,
+    watch_only: bool,
+}
+
+impl<'s> AliasResolver<'s> {
+    fn key(&self, key: &str) -> String {
+        let key = with_origin(key);
+        if self.watch_only {
+            to_public(&key)
+        } else {
+            key
+        }
+    }
+}
+
+impl<'s> Translator<String, String, String> for AliasResolver<'s> {
+    fn pk(&mut self, pk: &String) -> Result<String, String> {
+        if let Some(key) = self.state.resolve_alias(pk) {
+            return Ok(self.key(&key));
+        }
+
+        let is_key = DescriptorPublicKey::from_str(pk).is_ok()
+            || DescriptorSecretKey::from_str(pk).is_ok()
+            || PrivateKey::from_wif(pk).is_ok();
+        if is_key {
+            Ok(self.key(pk))
+        } else {
+            Err(format!("Unknown key or alias `{}`", pk))
+        }
+    }
+
+    fn sha256(&mut self, sha256: &String) -> Result<String, String> {
+        Ok(sha256.clone())
+    }
+
+    fn hash256(&mut self, hash256: &String) -> Result<String, String> {
+        Ok(hash256.clone())
+    }
+
+    fn ripemd160(&mut self, ripemd160: &String) -> Result<String, String> {
+        Ok(ripemd160.clone())
+    }
+
+    fn hash160(&mut self, hash160: &String) -> Result<String, String> {
+        Ok(hash160.clone())
+    }
+}
+
+/// Parses a policy, a descriptor or a wallet file, resolving aliases through the key manager.
+///
+/// Returns the resolved policy or descriptor, ready to be sent to the app. With `watch_only`
+/// private keys are dropped, so that the wallet can't sign.
+pub fn import(input: &str, state: &State, watch_only: bool) -> Result<String, String> {
+    // Wallet files exported by Elephant or other wallets contain a descriptor
+    let input = match wallet_export::import_wallet_file(input) {
+        Some(descriptor) => descriptor?,
+        None => input.trim().to_string(),
+    };
+    let input = input.as_str();
+    let mut resolver = AliasResolver { state, watch_only };
+
+    if let Ok(policy) = Concrete::<String>::from_str(input) {
+        let policy = policy.translate_pk(&mut resolver)?;
+        policy
+            .compile::<Tap>()
+            .map_err(|e| format!("Can't compile the policy: {}", e))?;
+
+        return Ok(policy.to_string());
+    }
+
+    let descriptor = Descriptor::<String>::from_str(input)
+        .map_err(|e| format!("Not a valid policy or descriptor: {}", e))?;
+    let descriptor = descriptor.translate_pk(&mut resolver)?.to_string();
+    Descriptor::parse_descriptor(&Secp256k1::new(), &descriptor)
+        .map_err(|e| format!("Invalid descriptor: {}", e))?;
+
+    Ok(descriptor)
+}
@@ -0,0 +1,439 @@
+use std::str::FromStr;
+
+use bdk::bitcoin::secp256k1::Secp256k1;
+use bdk::bitcoin::util::bip32::{DerivationPath, ExtendedPrivKey};
+use bdk::bitcoin::{Network, PrivateKey};
+use bdk::keys::bip39::{Language, Mnemonic};
+use bdk::miniscript::descriptor::{DescriptorPublicKey, DescriptorSecretKey, Wildcard};
+use bdk::miniscript::policy::Concrete;
+use bdk::miniscript::Translator;
+use serde::{Deserialize, Serialize};
+
+/// BIP86 account used for keys created from a mnemonic or a master xprv
+const BIP86_TESTNET_ACCOUNT: &str = "m/86'/1'/0'";
+
+/// A key of the key manager
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(from = "StoredKey")]
+pub enum Key {
+    /// Derived from the alias (and the room), the way the key manager always did
+    Alias(PrivateKey),
+    /// Descriptor secret key: a WIF or an xprv, with its origin
+    Secret(String),
+    /// Descriptor public key of somebody else: a raw or x-only public key, or an xpub with its
+    /// origin
+    Public(String),
+}
+
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum StoredKey {
+    /// Before keys could be imported they were always derived from the alias, and saved as WIF
+    Legacy(PrivateKey),
+    Current(CurrentKey),
+}
+
+#[derive(Deserialize)]
+enum CurrentKey {
+    Alias(PrivateKey),
+    Secret(String),
+    Public(String),
+}
+
+impl From<StoredKey> for Key {
+    fn from(stored: StoredKey) -> Self {
+        match stored {
+            StoredKey::Legacy(sk) | StoredKey::Current(CurrentKey::Alias(sk)) => Key::Alias(sk),
+            StoredKey::Current(CurrentKey::Secret(s)) => Key::Secret(s),
+            StoredKey::Current(CurrentKey::Public(s)) => Key::Public(s),
+        }
+    }
+}
+
+impl Key {
+    /// Key as it appears in a public descriptor
+    pub fn public(&self) -> String {
+        let secp = Secp256k1::new();
+        match self {
+            Key::Alias(sk) => sk.public_key(&secp).to_string(),
+            Key::Secret(s) => DescriptorSecretKey::from_str(s)
+                .and_then(|sk| sk.to_public(&secp))
+                .map(|pk| pk.to_string())
+                .unwrap_or_default(),
+            Key::Public(s) => s.clone(),
+        }
+    }
+
+    /// Key as it appears in a descriptor we can sign with
+    pub fn secret(&self) -> Option<String> {
+        match self {
+            Key::Alias(sk) => Some(sk.to_string()),
+            Key::Secret(s) => Some(s.clone()),
+            Key::Public(_) => None,
+        }
+    }
+
+    /// Whether `key` is one of the representations of this key, with or without origin
+    pub fn matches(&self, key: &str) -> bool {
+        let key = without_origin(key);
+        key == without_origin(&self.public())
+            || self.secret().as_deref().map(without_origin) == Some(key)
+    }
+
+    pub fn description(&self) -> &'static str {
+        match self {
+            Key::Alias(_) => "derived from the alias",
+            Key::Secret(s) if s.contains("prv") => "extended private key",
+            Key::Secret(_) => "private key",
+            Key::Public(s) if s.contains("pub") => "extended public key",
+            Key::Public(_) => "public key",
+        }
+    }
+}
+
+/// Parses somebody else's key: an xpub/tpub (optionally with origin and derivation), or a raw or
+/// x-only public key
+pub fn parse_remote(input: &str) -> Result<Key, String> {
+    let input = input.trim();
+    let key = DescriptorPublicKey::from_str(input)
+        .map_err(|e| format!("Not a valid public key: {}", e))?;
+
+    let key = match key {
+        // Bare xpubs are used as `xpub/0/*`, to get a different key for each address
+        DescriptorPublicKey::XPub(xkey)
+            if xkey.derivation_path.is_master() && xkey.wildcard == Wildcard::None =>
+        {
+            format!("{}/0/*", input)
+        }
+        _ => input.to_string(),
+    };
+
+    Ok(Key::Public(key))
+}
+
+/// Parses one of our own keys: a WIF, an xprv/tprv or a BIP39 mnemonic
+pub fn parse_local(input: &str) -> Result<Key, String> {
+    let input = input.trim();
+    if input.split_whitespace().count() > 1 {
+        let mnemonic = Mnemonic::parse_in(Language::English, input)
+            .map_err(|e| format!("Not a valid mnemonic: {}", e))?;
+        return Ok(from_mnemonic(&mnemonic));
+    }
+
+    if let Ok(xprv) = ExtendedPrivKey::from_str(input) {
+        if xprv.depth == 0 {
+            return Ok(bip86_account(&xprv));
+        }
+    }
+
+    DescriptorSecretKey::from_str(input)
+        .map_err(|e| format!("Not a valid WIF or extended private key: {}", e))?;
+
+    Ok(Key::Secret(input.to_string()))
+}
+
+/// Generates a new 12-word mnemonic
+pub fn generate_mnemonic() -> String {
+    let mut entropy = [0u8; 16];
+    getrandom::getrandom(&mut entropy).expect("random entropy");
+
+    Mnemonic::from_entropy(&entropy)
+        .expect("valid entropy length")
+        .to_string()
+}
+
+fn from_mnemonic(mnemonic: &Mnemonic) -> Key {
+    let seed = mnemonic.to_seed("");
+    let master = ExtendedPrivKey::new_master(Network::Testnet, &seed).expect("valid seed");
+
+    bip86_account(&master)
+}
+
+/// `[fingerprint/86'/1'/0']tprv/0/*` for a master key
+fn bip86_account(master: &ExtendedPrivKey) -> Key {
+    let secp = Secp256k1::new();
+    let path = DerivationPath::from_str(BIP86_TESTNET_ACCOUNT).expect("valid path");
+    let account = master.derive_priv(&secp, &path).expect("valid derivation");
+    let origin = path.to_string();
+
+    Key::Secret(format!(
+        "[{}/{}]{}/0/*",
+        master.fingerprint(&secp),
+        origin.trim_start_matches("m/"),
+        account
+    ))
+}
+
+/// Returns the public version of a secret key, anything else is returned unchanged
+pub fn to_public(key: &str) -> String {
+    DescriptorSecretKey::from_str(key)
+        .and_then(|sk| sk.to_public(&Secp256k1::new()))
+        .map(|pk| pk.to_string())
+        .unwrap_or_else(|_| key.to_string())
+}
+
+/// Adds the key origin `[fingerprint]` to master keys that don't have one, so that PSBTs carry
+/// derivation info for them: single keys and extended keys at depth 0. Their fingerprint is
+/// computed from the key itself and the path is empty. Derived extended keys without origin are
+/// returned unchanged, their master is unknown. Anything that isn't a key is returned unchanged.
+///
+/// Miniscript drops the origin of WIF keys when parsing a descriptor, but it computes the same
+/// fingerprint for them when filling the PSBT.
+pub fn with_origin(key: &str) -> String {
+    let secp = Secp256k1::new();
+    let master = |key: &DescriptorPublicKey| (key.master_fingerprint(), DerivationPath::master());
+
+    if let Ok(sk) = DescriptorSecretKey::from_str(key) {
+        let origin = sk.to_public(&secp).as_ref().map(master).ok();
+        return match sk {
+            DescriptorSecretKey::Single(mut single) if single.origin.is_none() => {
+                single.origin = origin;
+                DescriptorSecretKey::Single(single).to_string()
+            }
+            DescriptorSecretKey::XPrv(mut xprv)
+                if xprv.origin.is_none() && xprv.xkey.depth == 0 =>
+            {
+                xprv.origin = origin;
+                DescriptorSecretKey::XPrv(xprv).to_string()
+            }
+            _ => key.to_string(),
+        };
+    }
+
+    match DescriptorPublicKey::from_str(key) {
+        Ok(DescriptorPublicKey::Single(mut single)) if single.origin.is_none() => {
+            single.origin = Some(master(&DescriptorPublicKey::Single(single.clone())));
+            DescriptorPublicKey::Single(single).to_string()
+        }
+        Ok(DescriptorPublicKey::XPub(mut xpub))
+            if xpub.origin.is_none() && xpub.xkey.depth == 0 =>
+        {
+            xpub.origin = Some(master(&DescriptorPublicKey::XPub(xpub.clone())));
+            DescriptorPublicKey::XPub(xpub).to_string()
+        }
+        _ => key.to_string(),
+    }
+}
+
+/// Adds the key origin to every key of a policy
+pub fn policy_with_origins(policy: &Concrete<String>) -> Concrete<String> {
+    policy
+        .translate_pk(&mut OriginTranslator)
+        .expect("adding origins doesn't fail")
+}
+
+fn without_origin(key: &str) -> &str {
+    match key.strip_prefix('[') {
+        Some(rest) => rest.split_once(']').map(|(_, key)| key).unwrap_or(key),
+        None => key,
+    }
+}
+
+struct OriginTranslator;
+
+impl Translator<String, String, String> for OriginTranslator {
+    fn pk(&mut self, pk: &String) -> Result<String, String> {
+        Ok(with_origin(pk))
+    }
+
+    fn sha256(&mut self, sha256: &String) -> Result<String, String> {
+        Ok(sha256.clone())
+    }
+
+    fn hash256(&mut self, hash256: &String) -> Result<String, String> {
+        Ok(hash256.clone())
+    }
+
+    fn ripemd160(&mut self, ripemd160: &String) -> Result<String, String> {
+        Ok(ripemd160.clone())
+    }
+
+    fn hash160(&mut self, hash160: &String) -> Result<String, String> {
+        Ok(hash160.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk::bitcoin::secp256k1::SecretKey;
+    use bdk::bitcoin::util::bip32::ExtendedPubKey;
+
+    use super::*;
+
+    const MNEMONIC: &str =
+        "abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon abandon about";
+
+    fn wif() -> PrivateKey {
+        let sk = SecretKey::from_slice(&[1; 32]).unwrap();
+        PrivateKey::new(sk, Network::Testnet)
+    }
+
+    fn master() -> ExtendedPrivKey {
+        ExtendedPrivKey::new_master(Network::Testnet, &[2; 32]).unwrap()
+    }
+
+    /// Account key at depth 3, without origin
+    fn account() -> ExtendedPrivKey {
+        let path = DerivationPath::from_str(BIP86_TESTNET_ACCOUNT).unwrap();
+        master().derive_priv(&Secp256k1::new(), &path).unwrap()
+    }
+
+    #[test]
+    fn local_keys() {
+        let wif = wif().to_string();
+        assert_eq!(parse_local(&wif), Ok(Key::Secret(wif.clone())));
+        assert_eq!(parse_local(&format!(" {}\n", wif)), Ok(Key::Secret(wif)));
+
+        let account = format!("{}/0/*", account());
+        assert_eq!(parse_local(&account), Ok(Key::Secret(account.clone())));
+        let with_origin = format!(
+            "[{}/86'/1'/0']{}",
+            master().fingerprint(&Secp256k1::new()),
+            account
+        );
+        assert_eq!(
+            parse_local(&with_origin),
+            Ok(Key::Secret(with_origin.clone()))
+        );
+
+        // Master keys and mnemonics are used through their BIP86 account
+        assert_eq!(
+            parse_local(&master().to_string()),
+            Ok(Key::Secret(with_origin))
+        );
+        let from_mnemonic = parse_local(MNEMONIC).unwrap();
+        assert_eq!(
+            parse_local(&format!("  {}  ", MNEMONIC)),
+            Ok(from_mnemonic.clone())
+        );
+        assert!(matches!(&from_mnemonic, Key::Secret(s) if s.ends_with("/0/*")));
+    }
+
+    #[test]
+    fn invalid_local_keys() {
+        assert!(parse_local("").is_err());
+        assert!(parse_local("not a key")
+            .unwrap_err()
+            .contains("Not a valid mnemonic"));
+        // Wrong checksum
+        let mnemonic = MNEMONIC.replace("about", "abandon");
+        assert!(parse_local(&mnemonic)
+            .unwrap_err()
+            .contains("Not a valid mnemonic"));
+
+        let public = [
+            wif().public_key(&Secp256k1::new()).to_string(),
+            ExtendedPubKey::from_priv(&Secp256k1::new(), &master()).to_string(),
+        ];
+        for key in public {
+            assert!(parse_local(&key).unwrap_err().contains("Not a valid WIF"));
+        }
+    }
+
+    #[test]
+    fn remote_keys() {
+        let secp = Secp256k1::new();
+        let pk = wif().public_key(&secp).to_string();
+        assert_eq!(parse_remote(&pk), Ok(Key::Public(pk.clone())));
+        let x_only = pk[2..].to_string();
+        assert_eq!(parse_remote(&x_only), Ok(Key::Public(x_only)));
+
+        // Bare xpubs get a key for each address
+        let xpub = ExtendedPubKey::from_priv(&secp, &account()).to_string();
+        assert_eq!(
+            parse_remote(&xpub),
+            Ok(Key::Public(format!("{}/0/*", xpub)))
+        );
+        assert_eq!(
+            parse_remote(&format!(" {} ", xpub)),
+            Ok(Key::Public(format!("{}/0/*", xpub)))
+        );
+        for key in [
+            format!("{}/1/*", xpub),
+            format!("{}/0", xpub),
+            format!("[{}/86'/1'/0']{}/0/*", master().fingerprint(&secp), xpub),
+        ] {
+            assert_eq!(parse_remote(&key), Ok(Key::Public(key.clone())));
+        }
+    }
+
+    #[test]
+    fn invalid_remote_keys() {
+        for key in [
+            String::new(),
+            "not a key".to_string(),
+            wif().to_string(),
+            master().to_string(),
+            MNEMONIC.to_string(),
+            format!("05{}", "02".repeat(32)),
+        ] {
+            assert!(parse_remote(&key)
+                .unwrap_err()
+                .contains("Not a valid public key"));
+        }
+    }
+
+    #[test]
+    fn stored_keys() {
+        let sk = wif();
+        // Before keys could be imported, the alias key was saved as a plain WIF
+        let legacy = serde_json::to_string(&sk.to_string()).unwrap();
+        assert_eq!(
+            serde_json::from_str::<Key>(&legacy).unwrap(),
+            Key::Alias(sk)
+        );
+
+        for key in [
+            Key::Alias(sk),
+            Key::Secret(format!("{}/0/*", account())),
+            Key::Public(sk.public_key(&Secp256k1::new()).to_string()),
+        ] {
+            let json = serde_json::to_string(&key).unwrap();
+            assert_eq!(serde_json::from_str::<Key>(&json).unwrap(), key);
+        }
+        assert!(serde_json::from_str::<Key>(r#"{ "Other": "key" }"#).is_err());
+    }
+
+    #[test]
+    fn origins() {
+        let secp = Secp256k1::new();
+        // Test vector of BIP86
+        let key = parse_local(MNEMONIC).unwrap().secret().unwrap();
+        assert!(key.starts_with("[73c5da0a/86'/1'/0']tprv"), "{}", key);
+        assert!(key.ends_with("/0/*"));
+        assert_eq!(with_origin(&key), key);
+
+        let fingerprint = master().fingerprint(&secp);
+        let key = parse_local(&master().to_string())
+            .unwrap()
+            .secret()
+            .unwrap();
+        assert!(key.starts_with(&format!("[{}/86'/1'/0']", fingerprint)));
+
+        // Master keys are their own origin
+        let xprv = format!("{}/0/*", master());
+        assert_eq!(with_origin(&xprv), format!("[{}]{}", fingerprint, xprv));
+        let xpub = format!("{}/0/*", ExtendedPubKey::from_priv(&secp, &master()));
+        assert_eq!(with_origin(&xpub), format!("[{}]{}", fingerprint, xpub));
+        let pk = wif().public_key(&secp);
+        let wif = wif().to_string();
+        let fingerprint = DescriptorPublicKey::from_str(&pk.to_string())
+            .unwrap()
+            .master_fingerprint();
+        assert_eq!(with_origin(&wif), format!("[{}]{}", fingerprint, wif));
+        assert_eq!(
+            with_origin(&pk.to_string()),
+            format!("[{}]{}", fingerprint, pk)
+        );
+
+        // The master of derived keys is unknown
+        let xprv = format!("{}/0/*", account());
+        assert_eq!(with_origin(&xprv), xprv);
+        let xpub = parse_remote(&ExtendedPubKey::from_priv(&secp, &account()).to_string())
+            .unwrap()
+            .public();
+        assert!(xpub.ends_with("/0/*"));
+        assert_eq!(with_origin(&xpub), xpub);
+        assert_eq!(with_origin("not a key"), "not a key");
+    }
+}
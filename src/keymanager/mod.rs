@@ -1,53 +1,311 @@
 use std::cell::RefCell;
 use std::rc::Rc;
+use std::str::FromStr;
 
 use bdk::bitcoin::secp256k1::SecretKey;
 use bdk::bitcoin::{Network, PrivateKey};
+use bdk::miniscript::policy::Concrete;
+use gloo_file::{Blob, File, ObjectUrl};
 use serde::{Deserialize, Serialize};
 use wasm_bindgen::prelude::*;
-use web_sys::HtmlInputElement;
+use web_sys::{HtmlInputElement, HtmlSelectElement};
 use yew::prelude::*;
-use yew_agent::{Dispatched, Dispatcher};
+use yew_agent::{Bridge, Bridged};
 
 mod blockly;
+pub mod encryption;
+mod import;
+pub mod keys;
+mod policy_blocks;
+mod projects;
+mod share;
 mod storage;
+mod templates;
 
-use crate::evt::{EventBus, Request};
+use crate::compiler::Compilation;
+use crate::evt::{AppEvent, EventBus};
+use crate::hashlock::HashKind;
+use crate::timelock::TimeUnit;
 use blockly::*;
+use keys::Key;
+use projects::{Project, Projects};
+use share::SharedWallet;
+use storage::{LockedProjects, StoredProjects};
+use templates::{TemplateParams, TEMPLATES};
 
 #[derive(Debug, PartialEq, Properties)]
-pub struct KeymanagerProps;
+pub struct KeymanagerProps {
+    /// Compilation of the current policy, running in the background
+    #[prop_or_default]
+    pub compilation: Compilation,
+}
 
 #[derive(Debug, PartialEq)]
 pub enum KeymanagerMsg {
     FirstRender,
+
+    ProjectSelected(Event),
+    NewProject,
+    DuplicateProject,
+    DeleteProject,
+    ProjectRenamed(InputEvent),
+    ProjectDescriptionChanged(InputEvent),
+    EsploraUrlChanged(Event),
+    VersionLabelChanged(InputEvent),
+    SaveVersion,
+    RestoreVersion(usize),
+    ExportProject,
+    ProjectFilesSelected(Vec<File>),
+    ProjectImported(Result<Box<Project>, String>),
+    DismissStorageError,
+
+    PassphraseChanged(InputEvent),
+    PassphraseConfirmChanged(InputEvent),
+    SetPassphrase,
+    RemovePassphrase,
+    Lock,
+    Unlock,
+    /// The keys have been locked, from here or after some inactivity
+    Locked,
+
     NewInputNameChanged(InputEvent),
+    NewInputKeyChanged(InputEvent),
     AddKey,
 
     LocalKeyInputChanged(InputEvent),
     SetLocalKey,
+    LocalSecretInputChanged(InputEvent),
+    GenerateMnemonic,
+    ImportLocalKey,
+
+    RoomInputChanged(InputEvent),
+    SetRoom,
 
     RemoveKey(usize),
 
-    Compiled(String),
+    ImportInputChanged(InputEvent),
+    ImportWatchOnlyToggled,
+    ImportWallet,
+
+    TemplateSelected(Event),
+    TemplateDelayChanged(InputEvent),
+    TemplateSecretChanged(InputEvent),
+    ApplyTemplate,
+
+    ShareWallet,
+    SharedAliasChanged(InputEvent),
+    AcceptShared,
+    DismissShared,
+
+    WorkspaceChanged(Result<SerializedWorkspace, String>),
 }
 
 pub struct Keymanager {
     new_input_name: String,
+    new_input_key: String,
     local_key_input: String,
+    local_secret_input: String,
+    secret_error: Option<String>,
+    key_error: Option<String>,
+    room_input: String,
 
     state: Rc<RefCell<State>>,
 
-    compiled_cb: Closure<dyn FnMut(String)>,
+    compiled_cb: Closure<dyn FnMut(JsValue)>,
     dropdown_cb: Closure<dyn FnMut() -> JsValue>,
 
-    dispatcher: Dispatcher<EventBus>,
+    bus: Box<dyn Bridge<EventBus>>,
     workspace: Option<Workspace>,
+    last_policy: String,
+
+    projects: Projects,
+    version_label: String,
+    project_export: Option<ObjectUrl>,
+    project_error: Option<String>,
+    storage_error: Option<(storage::StorageError, ObjectUrl)>,
+
+    is_encrypted: bool,
+    locked: Option<LockedProjects>,
+    passphrase: String,
+    passphrase_confirm: String,
+    passphrase_error: Option<String>,
 
     is_editing: bool,
+    policy_error: Option<String>,
+
+    import_input: String,
+    import_watch_only: bool,
+    import_result: Option<Result<(), String>>,
+
+    template: usize,
+    template_params: TemplateParams,
+    template_result: Option<Result<(), String>>,
+
+    share_link: Option<Result<String, String>>,
+    pending_share: Option<Result<SharedWallet, String>>,
+    shared_alias: String,
 }
 
 impl Keymanager {
+    /// Copies the keys and blocks being edited into the current project, and persists it
+    fn save_project(&mut self) {
+        let project = self.projects.current_mut();
+        project.state = self.state.borrow().clone();
+        if let Some(workspace) = &self.workspace {
+            project.workspace = export_workspace(workspace);
+        }
+        project.modified = js_sys::Date::now();
+
+        self.persist();
+    }
+
+    /// Writes the projects to LocalStorage, encrypted if a passphrase is set
+    fn persist(&mut self) {
+        if self.locked.is_some() {
+            return;
+        }
+        if !self.is_encrypted {
+            storage::save_projects(&self.projects, None);
+            return;
+        }
+
+        match encryption::session_key() {
+            Some(key) => storage::save_projects(&self.projects, Some(&key)),
+            // The session expired: the last save is already encrypted
+            None => self.lock(),
+        }
+    }
+
+    /// Ends the session, the whole app forgets the keys when the lock event comes back
+    fn lock(&mut self) {
+        encryption::end_session();
+        self.bus.send(AppEvent::Locked);
+    }
+
+    /// Forgets the keys and every project, until the passphrase is entered again
+    fn forget(&mut self) {
+        self.locked = match storage::load_projects() {
+            Ok(Some(StoredProjects::Locked(locked))) => Some(locked),
+            other => {
+                log::error!(
+                    "Can't lock, the saved projects aren't encrypted: {:?}",
+                    other
+                );
+                return;
+            }
+        };
+
+        self.projects = Projects::new(Project::new(
+            String::new(),
+            State::empty(),
+            projects::empty_workspace(),
+        ));
+        *self.state.borrow_mut() = State::empty();
+        self.room_input = String::new();
+        if let Some(workspace) = &self.workspace {
+            import_workspace(workspace, &projects::empty_workspace());
+        }
+        self.last_policy = String::new();
+        self.bus.send(AppEvent::PolicyChanged(String::new()));
+    }
+
+    /// Loads the current project in the key manager and in Blockly
+    fn open_project(&mut self) {
+        let project = self.projects.current();
+        *self.state.borrow_mut() = project.state.clone();
+        self.room_input = project.state.room.clone().unwrap_or_default();
+        self.bus
+            .send(AppEvent::SettingsChanged(project.settings.clone()));
+        if let Some(workspace) = &self.workspace {
+            import_workspace(workspace, &project.workspace);
+        }
+
+        self.is_editing = false;
+        self.project_export = None;
+        self.project_error = None;
+        self.persist();
+    }
+
+    fn projects(&self, ctx: &Context<Self>) -> Html {
+        let onchange_project = ctx.link().callback(KeymanagerMsg::ProjectSelected);
+        let onclick_new = ctx.link().callback(|_| KeymanagerMsg::NewProject);
+        let onclick_duplicate = ctx.link().callback(|_| KeymanagerMsg::DuplicateProject);
+        let onclick_delete = ctx.link().callback(|_| KeymanagerMsg::DeleteProject);
+        let oninput_name = ctx.link().callback(KeymanagerMsg::ProjectRenamed);
+        let oninput_description = ctx
+            .link()
+            .callback(KeymanagerMsg::ProjectDescriptionChanged);
+        let onchange_url = ctx.link().callback(KeymanagerMsg::EsploraUrlChanged);
+        let oninput_label = ctx.link().callback(KeymanagerMsg::VersionLabelChanged);
+        let onclick_version = ctx.link().callback(|_| KeymanagerMsg::SaveVersion);
+        let onclick_export = ctx.link().callback(|_| KeymanagerMsg::ExportProject);
+        let onchange_file = ctx.link().callback(|e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            let files = crate::psbt_file::files_from_list(input.files());
+            input.set_value("");
+            KeymanagerMsg::ProjectFilesSelected(files)
+        });
+        let project = self.projects.current();
+
+        html! {
+            <div class="mb-4">
+                <h2>{ "Project" }</h2>
+                <div class="input-group mb-2">
+                    <select class="form-select" onchange={onchange_project}>
+                        { for self.projects.list.iter().enumerate().map(|(i, p)| html! {
+                            <option value={i.to_string()} selected={i == self.projects.current}>{ &p.name }</option>
+                        }) }
+                    </select>
+                    <button type="button" class="btn btn-outline-primary" title="New project" onclick={onclick_new}><i class="bi bi-plus-lg"></i></button>
+                    <button type="button" class="btn btn-outline-primary" title="Duplicate project" onclick={onclick_duplicate}><i class="bi bi-files"></i></button>
+                    <button type="button" class="btn btn-outline-danger" title="Delete project" onclick={onclick_delete} disabled={self.projects.list.len() == 1}><i class="bi bi-trash"></i></button>
+                </div>
+                <div class="input-group mb-2">
+                    <span class="input-group-text">{ "Name" }</span>
+                    <input type="text" class="form-control" oninput={oninput_name} value={project.name.clone()} />
+                </div>
+                <textarea class="form-control mb-2" rows="2" oninput={oninput_description} value={project.description.clone()} placeholder="Notes about this wallet"></textarea>
+                <div class="input-group mb-2">
+                    <span class="input-group-text">{ "Esplora" }</span>
+                    <input type="text" class="form-control" onchange={onchange_url} value={project.settings.esplora_url.clone()} />
+                </div>
+                <div class="form-text mb-2">
+                    { format!("Created {}, last modified {}", projects::format_date(project.created), projects::format_date(project.modified)) }
+                </div>
+                <div class="input-group mb-2">
+                    <input type="text" class="form-control" oninput={oninput_label} value={self.version_label.clone()} placeholder="Version label" />
+                    <button type="button" class="btn btn-outline-primary" onclick={onclick_version}><i class="bi bi-bookmark-plus"></i>{ " Save version" }</button>
+                </div>
+                if !project.versions.is_empty() {
+                    <ul class="list-group mb-2">
+                        { for project.versions.iter().enumerate().rev().map(|(i, version)| {
+                            let onclick_restore = ctx.link().callback(move |_| KeymanagerMsg::RestoreVersion(i));
+                            html! {
+                                <li class="list-group-item d-flex justify-content-between align-items-center">
+                                    <span>{ &version.label }<small class="text-muted">{ format!(" {}", projects::format_date(version.created)) }</small></span>
+                                    <button type="button" class="btn btn-sm btn-outline-secondary" onclick={onclick_restore}>{ "Restore" }</button>
+                                </li>
+                            }
+                        }) }
+                    </ul>
+                }
+                <div class="d-flex gap-2 align-items-center">
+                    <button type="button" class="btn btn-outline-secondary" onclick={onclick_export}><i class="bi bi-download"></i>{ " Export" }</button>
+                    if let Some(url) = &self.project_export {
+                        <a href={url.to_string()} download={project.file_name()}>{ project.file_name() }</a>
+                    }
+                    <label class="btn btn-outline-secondary mb-0">
+                        <i class="bi bi-upload"></i>{ " Import" }
+                        <input type="file" class="d-none" accept=".json" onchange={onchange_file} />
+                    </label>
+                </div>
+                if let Some(e) = &self.project_error {
+                    <div class="text-danger mt-2">{ e }</div>
+                }
+            </div>
+        }
+    }
+
     fn local_key(&self, ctx: &Context<Self>) -> Html {
         let state = self.state.borrow();
 
@@ -84,50 +342,479 @@ impl Keymanager {
             </div>
         }
     }
+
+    fn import_local_key(&self, ctx: &Context<Self>) -> Html {
+        let oninput = ctx.link().callback(KeymanagerMsg::LocalSecretInputChanged);
+        let onclick_generate = ctx.link().callback(|_| KeymanagerMsg::GenerateMnemonic);
+        let onclick_import = ctx.link().callback(|_| KeymanagerMsg::ImportLocalKey);
+        let description = self.state.borrow().local_key.as_ref().map(|(key, _)| {
+            format!(
+                "Your key is {}: {}",
+                key.description(),
+                keys::with_origin(&key.public())
+            )
+        });
+        let is_invalid = self.secret_error.is_some().then_some("is-invalid");
+
+        html! {
+            <div class="mt-2">
+                if let Some(description) = description {
+                    <div class="form-text text-break mb-1">{ description }</div>
+                }
+                <textarea {oninput} value={self.local_secret_input.clone()} rows="2" placeholder={"WIF, xprv/tprv or BIP39 mnemonic"} class={classes!("form-control", "mb-1", is_invalid)} />
+                if let Some(e) = &self.secret_error {
+                    <div class="invalid-feedback">{ e }</div>
+                }
+                <button type="button" class="btn btn-outline-secondary me-2" onclick={onclick_generate}>{ "Generate mnemonic" }</button>
+                <button type="button" class="btn btn-primary" onclick={onclick_import} disabled={self.local_secret_input.trim().is_empty()}>{ "Use this key" }</button>
+                if self.local_secret_input.trim().contains(' ') {
+                    <div class="form-text">{ "Write down the mnemonic: it's the only way to recover the key outside of this browser" }</div>
+                }
+            </div>
+        }
+    }
+
+    fn room(&self, ctx: &Context<Self>) -> Html {
+        let oninput = ctx.link().callback(KeymanagerMsg::RoomInputChanged);
+        let onclick_set = ctx.link().callback(|_| KeymanagerMsg::SetRoom);
+        let current = self.state.borrow().room.clone().unwrap_or_default();
+
+        html! {
+            <div style="margin-top: 20px">
+                <h2>{ "Room" }</h2>
+                <div class="row input-group">
+                    <input type="text" {oninput} value={self.room_input.clone()} placeholder={"Room code, leave empty to use no room"} class="form-control col-10" />
+                    <button type="button" class="btn btn-primary col-2" onclick={onclick_set} disabled={self.room_input.trim() == current}>
+                        <i class="bi bi-door-open"></i>
+                    </button>
+                </div>
+                <div class="form-text">
+                    if current.is_empty() {
+                        { "Keys are derived from the aliases only: the same alias gives the same key in every workshop" }
+                    } else {
+                        { format!("Keys are derived from the aliases and the room `{}`: share it with the other participants", current) }
+                    }
+                </div>
+            </div>
+        }
+    }
+
+    fn templates(&self, ctx: &Context<Self>) -> Html {
+        let onchange = ctx.link().callback(KeymanagerMsg::TemplateSelected);
+        let oninput_delay = ctx.link().callback(KeymanagerMsg::TemplateDelayChanged);
+        let oninput_secret = ctx.link().callback(KeymanagerMsg::TemplateSecretChanged);
+        let onclick_apply = ctx.link().callback(|_| KeymanagerMsg::ApplyTemplate);
+        let template = &TEMPLATES[self.template];
+        let feedback = match &self.template_result {
+            Some(Ok(())) => {
+                html! { <div class="form-text">{ "Template loaded, you can now edit the blocks" }</div> }
+            }
+            Some(Err(e)) => html! { <div class="text-danger">{ e }</div> },
+            None => html! {},
+        };
+
+        html! {
+            <div style="margin-top: 20px">
+                <h2>{ "Templates" }</h2>
+                <select class="form-select mb-2" {onchange}>
+                    { for TEMPLATES.iter().enumerate().map(|(i, t)| html! {
+                        <option value={i.to_string()} selected={i == self.template}>{ t.name }</option>
+                    }) }
+                </select>
+                <p class="form-text">{ template.description }</p>
+                if template.uses_delay {
+                    <div class="input-group mb-2">
+                        <span class="input-group-text">{ "Delay" }</span>
+                        <input type="number" min="1" max="65535" oninput={oninput_delay} value={self.template_params.delay.to_string()} class="form-control" />
+                        <span class="input-group-text">{ "blocks" }</span>
+                    </div>
+                }
+                if template.uses_secret {
+                    <input type="text" oninput={oninput_secret} value={self.template_params.secret.clone()} placeholder="Secret" class="form-control mb-2" />
+                }
+                <button type="button" class="btn btn-primary" onclick={onclick_apply}>
+                    <i class="bi bi-magic"></i>{ " Use template" }
+                </button>
+                { feedback }
+            </div>
+        }
+    }
+
+    fn import(&self, ctx: &Context<Self>) -> Html {
+        let oninput = ctx
+            .link()
+            .callback(move |e: InputEvent| KeymanagerMsg::ImportInputChanged(e));
+        let onclick_import = ctx.link().callback(|_| KeymanagerMsg::ImportWallet);
+        let onclick_watch_only = ctx
+            .link()
+            .callback(|_| KeymanagerMsg::ImportWatchOnlyToggled);
+        let (is_invalid, feedback) = match &self.import_result {
+            Some(Ok(())) => (
+                "",
+                html! { <div class="form-text">{ "Wallet imported" }</div> },
+            ),
+            Some(Err(e)) => (
+                "is-invalid",
+                html! { <div class="invalid-feedback">{ e }</div> },
+            ),
+            None => ("", html! {}),
+        };
+
+        html! {
+            <div style="margin-top: 20px">
+                <h2>{ "Import" }</h2>
+                <div class="row input-group has-validation">
+                    <input type={"text"} {oninput} value={self.import_input.clone()} placeholder={"or(pk(Alice),and(pk(Bob),older(10))), a descriptor or a wallet file"} class={classes!("form-control", "col-10", is_invalid)} />
+                    <button type={"button"} class="btn btn-primary col-2" onclick={onclick_import} disabled={self.import_input.trim().is_empty()}>
+                        <i class="bi bi-box-arrow-in-down"></i>
+                    </button>
+                    { feedback }
+                </div>
+                <div class="form-check mt-1">
+                    <input type="checkbox" id="import-watch-only" class="form-check-input" checked={self.import_watch_only} onclick={onclick_watch_only} />
+                    <label for="import-watch-only" class="form-check-label">{ "Watch-only: drop the private keys, to follow the balance of a wallet without being able to sign" }</label>
+                </div>
+            </div>
+        }
+    }
+
+    fn share(&self, ctx: &Context<Self>) -> Html {
+        let onclick_share = ctx.link().callback(|_| KeymanagerMsg::ShareWallet);
+        let result = match &self.share_link {
+            Some(Ok(link)) => html! {
+                <input type="text" class="form-control mt-2" readonly=true value={link.clone()} />
+            },
+            Some(Err(e)) => html! { <div class="text-danger mt-2">{ e }</div> },
+            None => html! {},
+        };
+
+        html! {
+            <div style="margin-top: 20px">
+                <h2>{ "Share" }</h2>
+                <button type="button" class="btn btn-primary" onclick={onclick_share}>
+                    <i class="bi bi-share"></i>{ " Create share link" }
+                </button>
+                { result }
+            </div>
+        }
+    }
+
+    fn unlock(&self, ctx: &Context<Self>) -> Html {
+        let oninput = ctx.link().callback(KeymanagerMsg::PassphraseChanged);
+        let onsubmit = ctx.link().callback(|e: FocusEvent| {
+            e.prevent_default();
+            KeymanagerMsg::Unlock
+        });
+        let is_invalid = self.passphrase_error.is_some().then_some("is-invalid");
+
+        html! {
+            <form {onsubmit}>
+                <h2>{ "Locked" }</h2>
+                <p>{ "Your keys are encrypted. Enter your passphrase to unlock them." }</p>
+                <div class="input-group has-validation">
+                    <input type="password" {oninput} value={self.passphrase.clone()} placeholder="Passphrase" class={classes!("form-control", is_invalid)} />
+                    <button type="submit" class="btn btn-primary" disabled={self.passphrase.is_empty()}><i class="bi bi-unlock"></i>{ " Unlock" }</button>
+                    if let Some(e) = &self.passphrase_error {
+                        <div class="invalid-feedback">{ e }</div>
+                    }
+                </div>
+            </form>
+        }
+    }
+
+    fn encryption(&self, ctx: &Context<Self>) -> Html {
+        if self.is_encrypted {
+            let onclick_lock = ctx.link().callback(|_| KeymanagerMsg::Lock);
+            let onclick_remove = ctx.link().callback(|_| KeymanagerMsg::RemovePassphrase);
+            return html! {
+                <div class="mb-4">
+                    <span class="form-text me-2">{ format!("Keys are encrypted, they get locked after {} minutes of inactivity", encryption::AUTO_LOCK_MS / 60_000.0) }</span>
+                    <button type="button" class="btn btn-sm btn-outline-primary me-2" onclick={onclick_lock}><i class="bi bi-lock"></i>{ " Lock" }</button>
+                    <button type="button" class="btn btn-sm btn-outline-danger" onclick={onclick_remove}>{ "Remove passphrase" }</button>
+                </div>
+            };
+        }
+
+        let oninput = ctx.link().callback(KeymanagerMsg::PassphraseChanged);
+        let oninput_confirm = ctx.link().callback(KeymanagerMsg::PassphraseConfirmChanged);
+        let onclick_set = ctx.link().callback(|_| KeymanagerMsg::SetPassphrase);
+        let is_invalid = self.passphrase_error.is_some().then_some("is-invalid");
+
+        html! {
+            <div class="mb-4">
+                <div class="input-group has-validation">
+                    <input type="password" {oninput} value={self.passphrase.clone()} placeholder="Passphrase" class="form-control" />
+                    <input type="password" oninput={oninput_confirm} value={self.passphrase_confirm.clone()} placeholder="Confirm passphrase" class={classes!("form-control", is_invalid)} />
+                    <button type="button" class="btn btn-outline-primary" onclick={onclick_set} disabled={self.passphrase.is_empty()}><i class="bi bi-shield-lock"></i>{ " Encrypt keys" }</button>
+                    if let Some(e) = &self.passphrase_error {
+                        <div class="invalid-feedback">{ e }</div>
+                    }
+                </div>
+            </div>
+        }
+    }
+
+    fn compilation(&self, ctx: &Context<Self>) -> Html {
+        match &ctx.props().compilation {
+            Compilation::Idle => html! {},
+            Compilation::Running => html! {
+                <div class="alert alert-info">
+                    <span class="spinner-border spinner-border-sm me-2" role="status"></span>
+                    { "Compiling… A running compilation can't be cancelled, your latest edits are compiled once it's done." }
+                </div>
+            },
+            Compilation::Done(Err(e)) => html! {
+                <div class="alert alert-warning">{ format!("Can't compile the policy: {}", e) }</div>
+            },
+            Compilation::Done(Ok(weights)) => match weights.max {
+                Some(max) => html! {
+                    <div class="form-text mb-3" title="Weight of the witness, in weight units">
+                        { format!("Spending costs up to {} WU", max) }
+                        <ul class="mb-0">
+                        { for weights.leaves.iter().map(|(leaf, weight)| html! {
+                            <li><code>{ leaf }</code>{ format!(": {} WU", weight) }</li>
+                        })}
+                        </ul>
+                    </div>
+                },
+                None => html! {},
+            },
+        }
+    }
+
+    fn storage_error(&self, ctx: &Context<Self>) -> Html {
+        let (error, backup) = match &self.storage_error {
+            Some(error) => error,
+            None => return html! {},
+        };
+        let onclick_dismiss = ctx.link().callback(|_| KeymanagerMsg::DismissStorageError);
+
+        html! {
+            <div class="alert alert-danger alert-dismissible">
+                { error.to_string() }
+                <br/>
+                <a href={backup.to_string()} download="elephant-backup.json">{ "Download a copy" }</a>
+                <button type="button" class="btn-close" onclick={onclick_dismiss}></button>
+            </div>
+        }
+    }
+
+    fn pending_share(&self, ctx: &Context<Self>) -> Html {
+        let shared = match &self.pending_share {
+            Some(Ok(shared)) => shared,
+            Some(Err(e)) => {
+                let onclick_dismiss = ctx.link().callback(|_| KeymanagerMsg::DismissShared);
+                return html! {
+                    <div class="alert alert-danger alert-dismissible">
+                        { format!("Invalid shared wallet link: {}", e) }
+                        <button type="button" class="btn-close" onclick={onclick_dismiss}></button>
+                    </div>
+                };
+            }
+            None => return html! {},
+        };
+
+        let oninput_alias = ctx
+            .link()
+            .callback(move |e: InputEvent| KeymanagerMsg::SharedAliasChanged(e));
+        let onclick_accept = ctx.link().callback(|_| KeymanagerMsg::AcceptShared);
+        let onclick_dismiss = ctx.link().callback(|_| KeymanagerMsg::DismissShared);
+
+        html! {
+            <div class="alert alert-info">
+                <p>
+                    { format!("You opened a shared wallet between {}", shared.participants.join(", ")) }
+                    if let Some(room) = &shared.room {
+                        { format!(" in the room `{}`", room) }
+                    }
+                    { ". Who are you?" }
+                </p>
+                <input type="text" list="shared-participants" oninput={oninput_alias} value={self.shared_alias.clone()} placeholder="Your name" class="form-control mb-2" />
+                <datalist id="shared-participants">
+                    { for shared.participants.iter().map(|p| html! { <option value={p.clone()} /> }) }
+                </datalist>
+                <button type="button" class="btn btn-primary me-2" onclick={onclick_accept} disabled={self.shared_alias.trim().is_empty()}>{ "Open wallet" }</button>
+                <button type="button" class="btn btn-secondary" onclick={onclick_dismiss}>{ "Ignore" }</button>
+            </div>
+        }
+    }
+}
+
+fn hash_lock_block(kind: HashKind) -> BlocklyBlock {
+    let (message0, tooltip) = match kind {
+        HashKind::Sha256 => (
+            "SHA256 %1 %2 %3",
+            "Require the preimage of a SHA256 hash to satisfy this fragment",
+        ),
+        HashKind::Hash256 => (
+            "HASH256 %1 %2 %3",
+            "Require the preimage of a double-SHA256 hash to satisfy this fragment",
+        ),
+        HashKind::Ripemd160 => (
+            "RIPEMD160 %1 %2 %3",
+            "Require the preimage of a RIPEMD160 hash to satisfy this fragment",
+        ),
+        HashKind::Hash160 => (
+            "HASH160 %1 %2 %3",
+            "Require the preimage of a HASH160 (RIPEMD160 of SHA256) hash to satisfy this fragment",
+        ),
+    };
+
+    BlocklyBlock {
+        ty: kind.name(),
+        message0,
+        args0: vec![
+            BlocklyBlockArg::field_dropdown("Mode", vec![("hash", "hash"), ("secret", "secret")]),
+            BlocklyBlockArg::field_input("Value", ""),
+            BlocklyBlockArg::dummy(),
+        ],
+        output: None,
+        next_statement: Some(ValueType::Policy), // disabled on creation by blockly-ext
+        previous_statement: Some(ValueType::Policy),
+        inputs_inline: false,
+        tooltip: Some(tooltip),
+        colour: 290,
+        extensions: vec!["allow_chain_in_thresh"],
+    }
 }
 
-#[derive(Debug, Clone, Serialize, Deserialize)]
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
 pub struct State {
-    local_key: Option<(PrivateKey, String)>,
-    keys: Vec<(String, PrivateKey)>,
+    local_key: Option<(Key, String)>,
+    keys: Vec<(String, Key)>,
     remote_keys_serial: usize,
+    /// Workshop room code mixed into the derivation of the keys
+    #[serde(default)]
+    room: Option<String>,
+}
+
+/// Derives the key of an alias: `sha256(alias)` without a room, like before rooms existed, and
+/// `HMAC-SHA256(room, alias)` otherwise
+fn derive_key(alias: &str, room: Option<&str>) -> PrivateKey {
+    use bdk::bitcoin::hashes::{hmac, sha256, Hash, HashEngine};
+
+    let hash = match room {
+        None => sha256::Hash::hash(alias.as_bytes()).into_inner(),
+        Some(room) => {
+            let mut engine = hmac::HmacEngine::<sha256::Hash>::new(room.as_bytes());
+            engine.input(alias.as_bytes());
+            hmac::Hmac::from_engine(engine).into_inner()
+        }
+    };
+
+    PrivateKey {
+        compressed: true,
+        network: Network::Testnet,
+        inner: SecretKey::from_slice(&hash).expect("32 bytes, within curve order"),
+    }
 }
 
 impl State {
     pub fn add_alias(&mut self, alias: String) {
-        use bdk::bitcoin::hashes::Hash;
-
-        let hash = bdk::bitcoin::hashes::sha256::Hash::hash(alias.as_bytes());
-        let sk = PrivateKey {
-            compressed: true,
-            network: Network::Testnet,
-            inner: SecretKey::from_slice(&hash).expect("32 bytes, within curve order"),
-        };
+        let sk = derive_key(&alias, self.room.as_deref());
+        self.keys.push((alias, Key::Alias(sk)));
+    }
 
-        self.keys.push((alias, sk));
+    /// Adds somebody else's public key
+    pub fn add_remote(&mut self, alias: String, key: Key) {
+        self.keys.push((alias, key));
     }
 
     pub fn set_local(&mut self, alias: String) {
-        use bdk::bitcoin::hashes::Hash;
+        let sk = derive_key(&alias, self.room.as_deref());
+        self.local_key = Some((Key::Alias(sk), alias));
+    }
 
-        let hash = bdk::bitcoin::hashes::sha256::Hash::hash(alias.as_bytes());
-        let sk = PrivateKey {
-            compressed: true,
-            network: Network::Testnet,
-            inner: SecretKey::from_slice(&hash).expect("32 bytes, within curve order"),
-        };
+    /// Replaces the local key with an imported one, keeping the alias
+    pub fn set_local_key(&mut self, key: Key) {
+        let alias = self
+            .local_key
+            .take()
+            .map(|(_, alias)| alias)
+            .unwrap_or_else(|| "Me".to_string());
+        self.local_key = Some((key, alias));
+    }
+
+    /// Changes the room and derives every alias key again. Imported keys don't change. Returns
+    /// the old and new public keys, to update the blocks using them.
+    pub fn set_room(&mut self, room: Option<String>) -> Vec<(String, String)> {
+        self.room = room;
+
+        let mut changes = vec![];
+        let keys = self
+            .local_key
+            .iter_mut()
+            .map(|(key, alias)| (&*alias, key))
+            .chain(self.keys.iter_mut().map(|(alias, key)| (&*alias, key)));
+        for (alias, key) in keys {
+            if let Key::Alias(_) = key {
+                let new_key = Key::Alias(derive_key(alias, self.room.as_deref()));
+                changes.push((key.public(), new_key.public()));
+                *key = new_key;
+            }
+        }
+
+        changes
+    }
+
+    /// Returns the key an alias refers to: the secret of our local key, or the public key of
+    /// somebody else
+    pub fn resolve_alias(&self, alias: &str) -> Option<String> {
+        if let Some((key, _)) = self.local_key.as_ref().filter(|(_, a)| a == alias) {
+            return key.secret();
+        }
+
+        self.keys
+            .iter()
+            .find(|(a, _)| a == alias)
+            .map(|(_, key)| key.public())
+    }
+
+    pub fn local_pubkey(&self) -> Option<String> {
+        self.local_key.as_ref().map(|(key, _)| key.public())
+    }
 
-        self.local_key = Some((sk, alias));
+    /// Public keys of the participants whose key isn't derived from their alias, needed to
+    /// recreate the wallet elsewhere
+    pub fn imported_pubkeys(&self) -> Vec<(String, String)> {
+        self.local_key
+            .iter()
+            .map(|(key, alias)| (alias, key))
+            .chain(self.keys.iter().map(|(alias, key)| (alias, key)))
+            .filter(|(_, key)| !matches!(key, Key::Alias(_)))
+            .map(|(alias, key)| (alias.clone(), key.public()))
+            .collect()
+    }
+
+    /// Local alias first, followed by every remote alias
+    pub fn participants(&self) -> Vec<String> {
+        let mut participants = self
+            .local_key
+            .iter()
+            .map(|(_, alias)| alias.clone())
+            .collect::<Vec<_>>();
+        for (alias, _) in &self.keys {
+            if !participants.contains(alias) {
+                participants.push(alias.clone());
+            }
+        }
+
+        participants
     }
 }
 
 impl State {
-    fn new() -> Self {
-        let mut state = State {
+    fn empty() -> Self {
+        State {
             local_key: None,
             keys: Vec::new(),
             remote_keys_serial: 0,
-        };
+            room: None,
+        }
+    }
+
+    fn new() -> Self {
+        let mut state = State::empty();
         state.add_alias("Alice".to_string());
 
         state
@@ -140,8 +827,9 @@ impl Component for Keymanager {
 
     fn create(ctx: &Context<Self>) -> Self {
         let link = ctx.link().clone();
-        let compiled_cb = Closure::new(move |s: String| {
-            link.send_message(KeymanagerMsg::Compiled(s));
+        let compiled_cb = Closure::new(move |workspace: JsValue| {
+            let workspace = serde_wasm_bindgen::from_value(workspace).map_err(|e| e.to_string());
+            link.send_message(KeymanagerMsg::WorkspaceChanged(workspace));
         });
 
         let state = Rc::new(RefCell::new(State::new()));
@@ -151,10 +839,7 @@ impl Component for Keymanager {
             let dropdown = state_cloned
                 .keys
                 .iter()
-                .map(|(k, v)| {
-                    let key = v.public_key(&bdk::bitcoin::secp256k1::Secp256k1::new());
-                    (k, key)
-                })
+                .map(|(alias, key)| (alias, key.public()))
                 .collect::<Vec<_>>();
             log::debug!("{:?}", dropdown);
             serde_wasm_bindgen::to_value(&dropdown).unwrap()
@@ -162,17 +847,55 @@ impl Component for Keymanager {
 
         Keymanager {
             new_input_name: String::new(),
+            new_input_key: String::new(),
             local_key_input: String::new(),
+            local_secret_input: String::new(),
+            secret_error: None,
+            key_error: None,
+            room_input: String::new(),
 
             state,
 
             compiled_cb,
             dropdown_cb,
 
-            dispatcher: EventBus::dispatcher(),
+            bus: EventBus::bridge(ctx.link().batch_callback(|event| match event {
+                AppEvent::Locked => Some(KeymanagerMsg::Locked),
+                _ => None,
+            })),
             workspace: None,
+            last_policy: String::new(),
+
+            projects: Projects::new(Project::new(
+                "My wallet".to_string(),
+                State::new(),
+                projects::empty_workspace(),
+            )),
+            version_label: String::new(),
+            project_export: None,
+            project_error: None,
+            storage_error: None,
+
+            is_encrypted: false,
+            locked: None,
+            passphrase: String::new(),
+            passphrase_confirm: String::new(),
+            passphrase_error: None,
 
             is_editing: false,
+            policy_error: None,
+
+            import_input: String::new(),
+            import_watch_only: false,
+            import_result: None,
+
+            template: 0,
+            template_params: TemplateParams::default(),
+            template_result: None,
+
+            share_link: None,
+            pending_share: None,
+            shared_alias: String::new(),
         }
     }
 
@@ -180,6 +903,7 @@ impl Component for Keymanager {
         let oninput_name = ctx
             .link()
             .callback(move |e: InputEvent| KeymanagerMsg::NewInputNameChanged(e));
+        let oninput_key = ctx.link().callback(KeymanagerMsg::NewInputKeyChanged);
         let onclick_add = ctx.link().callback(|_| KeymanagerMsg::AddKey);
 
         html! {
@@ -189,20 +913,32 @@ impl Component for Keymanager {
                         <div id="blocklyDiv" style="position: absolute;"></div>
                     </div>
                     <div class="col-6 px-5">
+                        { self.storage_error(ctx) }
+                        if self.locked.is_some() {
+                            { self.unlock(ctx) }
+                        } else {
+                        { self.pending_share(ctx) }
+                        { self.projects(ctx) }
+                        { self.encryption(ctx) }
+                        if let Some(e) = &self.policy_error {
+                            <div class="alert alert-warning">{ e }</div>
+                        }
+                        { self.compilation(ctx) }
                         <div>
                             <h2>{ "Local Key" }</h2>
                             { self.local_key(ctx) }
+                            { self.import_local_key(ctx) }
                         </div>
+                        { self.room(ctx) }
 
                         <div style="margin-top: 20px">
                         <h2>{ "Remote Keys" }</h2>
-                        { for self.state.borrow().keys.iter().enumerate().map(|(i, (name, _key))| {
+                        { for self.state.borrow().keys.iter().enumerate().map(|(i, (name, key))| {
                                 let remove_onclick = ctx.link().callback_once(move |_| KeymanagerMsg::RemoveKey(i));
-                                // let key = key.public_key(&bdk::bitcoin::secp256k1::Secp256k1::new());
                                 html! {
                                     <div class="input-group row mb-1">
-                                        <input type={"text"} disabled=true value={name.clone()} class="form-control col-10" />
-                                        // <span class="col-7">{ key.clone() }</span>
+                                        <input type={"text"} disabled=true value={name.clone()} title={keys::with_origin(&key.public())} class="form-control col-7" />
+                                        <span class="input-group-text col-3">{ key.description() }</span>
                                         <button type="button" onclick={remove_onclick} disabled={self.state.borrow().keys.len() == 1} class="col-2 btn btn-primary"><i class="bi bi-trash"></i></button>
                                     </div>
                                 }
@@ -210,18 +946,26 @@ impl Component for Keymanager {
                         }
                         </div>
                         <div class="row input-group has-validation">
-                            <input type={"text"} oninput={oninput_name} placeholder={"Name"} value={self.new_input_name.clone()} class="col-10 form-control" />
+                            <input type={"text"} oninput={oninput_name} placeholder={"Name"} value={self.new_input_name.clone()} class="col-4 form-control" />
+                            <input type={"text"} oninput={oninput_key} placeholder={"Public key or xpub (optional)"} value={self.new_input_key.clone()} class={classes!("col-6", "form-control", self.key_error.is_some().then_some("is-invalid"))} />
                             <button type={"button"} class="btn btn-primary col-2" onclick={onclick_add} disabled={self.new_input_name.is_empty()}>
                               <i class="bi bi-check-lg"></i>
                             </button>
+                            if let Some(e) = &self.key_error {
+                                <div class="invalid-feedback">{ e }</div>
+                            }
                         </div>
+                        { self.templates(ctx) }
+                        { self.import(ctx) }
+                        { self.share(ctx) }
+                        }
                     </div>
                 </div>
             </div>
         }
     }
 
-    fn update(&mut self, _ctx: &Context<Self>, msg: Self::Message) -> bool {
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
         match msg {
             KeymanagerMsg::FirstRender => {
                 let control_flow = vec![
@@ -351,18 +1095,62 @@ impl Component for Keymanager {
                         next_statement: Some(ValueType::Policy), // disabled on creation by blockly-ext
                         previous_statement: Some(ValueType::Policy),
                         inputs_inline: false,
-                        tooltip: Some("Add a relative timelock expressed in absolute block height"),
+                        tooltip: Some("Add an absolute timelock expressed as a block height"),
                         colour: 150,
                         extensions: vec!["allow_chain_in_thresh"],
                     },
+                    BlocklyBlock {
+                        ty: "older_time",
+                        message0: "Older %1 %2 %3",
+                        args0: vec![
+                            BlocklyBlockArg::field_number("value", "1"),
+                            BlocklyBlockArg::field_dropdown(
+                                "Unit",
+                                TimeUnit::all()
+                                    .iter()
+                                    .rev()
+                                    .map(|u| (u.name(), u.name()))
+                                    .collect(),
+                            ),
+                            BlocklyBlockArg::dummy(),
+                        ],
+                        output: None,
+                        next_statement: Some(ValueType::Policy), // disabled on creation by blockly-ext
+                        previous_statement: Some(ValueType::Policy),
+                        inputs_inline: false,
+                        tooltip: Some("Add a relative timelock expressed as a duration, rounded up to a multiple of 512 seconds (at most ~388 days)"),
+                        colour: 150,
+                        extensions: vec!["allow_chain_in_thresh"],
+                    },
+                    BlocklyBlock {
+                        ty: "after_time",
+                        message0: "After date %1 UTC %2",
+                        args0: vec![
+                            BlocklyBlockArg::field_input("Date", "2030-01-01 00:00"),
+                            BlocklyBlockArg::dummy(),
+                        ],
+                        output: None,
+                        next_statement: Some(ValueType::Policy), // disabled on creation by blockly-ext
+                        previous_statement: Some(ValueType::Policy),
+                        inputs_inline: false,
+                        tooltip: Some("Add an absolute timelock expressed as a UTC date (YYYY-MM-DD HH:MM)"),
+                        colour: 150,
+                        extensions: vec!["allow_chain_in_thresh", "date_picker"],
+                    },
                 ];
 
+                let hash_locks = HashKind::all()
+                    .into_iter()
+                    .map(hash_lock_block)
+                    .collect::<Vec<_>>();
+
                 define_blocks(
                     control_flow
                         .iter()
                         .chain(begin.iter())
                         .chain(keys.iter())
-                        .chain(leaves.iter()),
+                        .chain(leaves.iter())
+                        .chain(hash_locks.iter()),
                 );
 
                 let workspace = inject_blockly(
@@ -382,40 +1170,90 @@ impl Component for Keymanager {
                                     leaves.iter().map(|b| b.ty),
                                 ),
                                 BlocklyToolboxCategory::new("Keys", 65, keys.iter().map(|b| b.ty)),
+                                BlocklyToolboxCategory::new(
+                                    "Hash Locks",
+                                    290,
+                                    hash_locks.iter().map(|b| b.ty),
+                                ),
                             ],
                         },
                         trashcan: true,
                     },
                 );
 
-                if let Some(state) = storage::load() {
-                    *self.state.borrow_mut() = state;
-                } else {
-                    storage::save(&self.state.borrow());
-                }
-
                 init_js(&workspace, &self.compiled_cb, &self.dropdown_cb);
 
-                enable_backup();
-                restore_blocks();
-
-                insert_begin(&workspace);
-
+                let loaded = storage::load_projects().map(|stored| {
+                    stored.unwrap_or_else(|| {
+                        StoredProjects::Plain(Projects::new(Project::new(
+                            "My wallet".to_string(),
+                            State::new(),
+                            serde_json::Value::Null,
+                        )))
+                    })
+                });
+                match loaded {
+                    Ok(StoredProjects::Plain(projects)) => self.projects = projects,
+                    Ok(StoredProjects::Locked(locked)) => {
+                        self.is_encrypted = true;
+                        // Still unlocked if we come back from another tab
+                        match encryption::session_key()
+                            .map(|key| storage::unlock_projects_with_key(&locked, &key))
+                        {
+                            Some(Ok(projects)) => self.projects = projects,
+                            _ => self.locked = Some(locked),
+                        }
+                    }
+                    Err(e) => {
+                        // Start from a fresh project, the data has been backed up by `storage`
+                        log::error!("{}", e);
+                        let backup = ObjectUrl::from(Blob::new(e.raw.as_str()));
+                        self.storage_error = Some((e, backup));
+                    }
+                }
+                // Designs made before projects existed have their blocks in the Blockly backup
+                let project = self.projects.current_mut();
+                if project.workspace.is_null() {
+                    restore_blocks();
+                    insert_begin(&workspace);
+                    project.workspace = export_workspace(&workspace);
+                    project.created = js_sys::Date::now();
+                    project.modified = project.created;
+                }
                 self.workspace = Some(workspace);
+                if self.locked.is_none() {
+                    self.open_project();
+                }
 
-                false
+                self.pending_share = SharedWallet::from_fragment(&share::current_fragment());
+                true
             }
             KeymanagerMsg::NewInputNameChanged(e) => {
                 self.new_input_name = e.target_unchecked_into::<HtmlInputElement>().value();
                 true
             }
+            KeymanagerMsg::NewInputKeyChanged(e) => {
+                self.new_input_key = e.target_unchecked_into::<HtmlInputElement>().value();
+                self.key_error = None;
+                true
+            }
             KeymanagerMsg::AddKey => {
-                self.state
-                    .borrow_mut()
-                    .add_alias(self.new_input_name.clone());
+                let alias = self.new_input_name.clone();
+                if self.new_input_key.trim().is_empty() {
+                    self.state.borrow_mut().add_alias(alias);
+                } else {
+                    match keys::parse_remote(&self.new_input_key) {
+                        Ok(key) => self.state.borrow_mut().add_remote(alias, key),
+                        Err(e) => {
+                            self.key_error = Some(e);
+                            return true;
+                        }
+                    }
+                }
                 self.new_input_name = String::new();
+                self.new_input_key = String::new();
 
-                storage::save(&self.state.borrow());
+                self.save_project();
 
                 true
             }
@@ -430,7 +1268,7 @@ impl Component for Keymanager {
                         .set_local(self.local_key_input.clone());
                     self.local_key_input = String::new();
 
-                    storage::save(&self.state.borrow());
+                    self.save_project();
 
                     self.is_editing = false;
                 } else {
@@ -446,19 +1284,413 @@ impl Component for Keymanager {
 
                 true
             }
+            KeymanagerMsg::LocalSecretInputChanged(e) => {
+                self.local_secret_input = e.target_unchecked_into::<HtmlInputElement>().value();
+                self.secret_error = None;
+                true
+            }
+            KeymanagerMsg::GenerateMnemonic => {
+                self.local_secret_input = keys::generate_mnemonic();
+                true
+            }
+            KeymanagerMsg::ImportLocalKey => {
+                let key = match keys::parse_local(&self.local_secret_input) {
+                    Ok(key) => key,
+                    Err(e) => {
+                        self.secret_error = Some(e);
+                        return true;
+                    }
+                };
+                let old_pubkey = self.state.borrow().local_pubkey();
+                let new_pubkey = key.public();
+                self.state.borrow_mut().set_local_key(key);
+                self.local_secret_input = String::new();
+
+                // `my_key` blocks follow the new key, but a shared wallet may still point to the old one
+                if let (Some(old), Some(workspace)) = (old_pubkey, &self.workspace) {
+                    let mut blocks = export_workspace(workspace);
+                    share::replace_keys(&mut blocks, &[(old, new_pubkey)]);
+                    import_workspace(workspace, &blocks);
+                }
+                self.save_project();
+                true
+            }
+            KeymanagerMsg::RoomInputChanged(e) => {
+                self.room_input = e.target_unchecked_into::<HtmlInputElement>().value();
+                true
+            }
+            KeymanagerMsg::SetRoom => {
+                let room = Some(self.room_input.trim().to_string()).filter(|r| !r.is_empty());
+                let changes = self.state.borrow_mut().set_room(room);
+
+                // `key` blocks contain public keys, which have just changed
+                if let Some(workspace) = &self.workspace {
+                    let mut blocks = export_workspace(workspace);
+                    share::replace_keys(&mut blocks, &changes);
+                    import_workspace(workspace, &blocks);
+                }
+                self.save_project();
+                true
+            }
             KeymanagerMsg::RemoveKey(i) => {
                 self.state.borrow_mut().keys.remove(i);
-                storage::save(&self.state.borrow());
+                self.save_project();
 
                 true
             }
 
-            KeymanagerMsg::Compiled(mut desc) => {
-                if let Some((local_key, _)) = self.state.borrow().local_key {
-                    desc = desc.replace("_MY_KEY", &local_key.to_string());
+            KeymanagerMsg::ImportInputChanged(e) => {
+                self.import_input = e.target_unchecked_into::<HtmlInputElement>().value();
+                self.import_result = None;
+                true
+            }
+            KeymanagerMsg::ImportWatchOnlyToggled => {
+                self.import_watch_only = !self.import_watch_only;
+                self.import_result = None;
+                true
+            }
+            KeymanagerMsg::ImportWallet => {
+                let res = import::import(
+                    &self.import_input,
+                    &self.state.borrow(),
+                    self.import_watch_only,
+                );
+                log::info!("Import: {:?}", res);
+                self.import_result = Some(res.map(|imported| {
+                    self.bus.send(AppEvent::PolicyChanged(imported));
+                }));
+
+                // Policies can also be shown as blocks, so that they can be edited further. Not
+                // for watch-only wallets, the blocks would bring the local key back.
+                let policy = Concrete::<String>::from_str(self.import_input.trim());
+                if let (Some(Ok(())), Ok(policy), Some(workspace), false) = (
+                    &self.import_result,
+                    policy,
+                    &self.workspace,
+                    self.import_watch_only,
+                ) {
+                    match policy_blocks::policy_to_workspace(&policy, &self.state.borrow()) {
+                        Ok(blocks) => import_workspace(workspace, &blocks),
+                        Err(e) => log::warn!("Can't show the imported policy as blocks: {}", e),
+                    }
+                }
+                true
+            }
+            KeymanagerMsg::TemplateSelected(e) => {
+                let value = e.target_unchecked_into::<HtmlSelectElement>().value();
+                self.template = value.parse().unwrap_or(0).min(TEMPLATES.len() - 1);
+                self.template_result = None;
+                true
+            }
+            KeymanagerMsg::TemplateDelayChanged(e) => {
+                let value = e.target_unchecked_into::<HtmlInputElement>().value();
+                self.template_params.delay = value.parse().unwrap_or(0);
+                self.template_result = None;
+                true
+            }
+            KeymanagerMsg::TemplateSecretChanged(e) => {
+                self.template_params.secret = e.target_unchecked_into::<HtmlInputElement>().value();
+                self.template_result = None;
+                true
+            }
+            KeymanagerMsg::ApplyTemplate => {
+                let workspace = match &self.workspace {
+                    Some(workspace) => workspace,
+                    None => return false,
+                };
+                let state = self.state.borrow();
+                self.template_result = Some(
+                    TEMPLATES[self.template]
+                        .instantiate(&state, &self.template_params)
+                        .and_then(|policy| policy_blocks::policy_to_workspace(&policy, &state))
+                        .map(|blocks| import_workspace(workspace, &blocks)),
+                );
+                true
+            }
+            KeymanagerMsg::ShareWallet => {
+                let workspace = match &self.workspace {
+                    Some(workspace) => workspace,
+                    None => return false,
+                };
+                let state = self.state.borrow();
+                let mut workspace = export_workspace(workspace);
+                share::hash_secrets(&mut workspace);
+
+                self.share_link = match state.local_pubkey() {
+                    None if share::uses_my_key(&workspace) => Some(Err(
+                        "Set your local key before sharing the wallet".to_string(),
+                    )),
+                    local_pubkey => {
+                        if let Some(pubkey) = local_pubkey {
+                            share::my_key_to_key(&mut workspace, &pubkey);
+                        }
+                        let shared = SharedWallet {
+                            participants: state.participants(),
+                            room: state.room.clone(),
+                            pubkeys: state.imported_pubkeys(),
+                            workspace,
+                        };
+                        Some(Ok(shared.to_link(&share::base_url())))
+                    }
+                };
+
+                true
+            }
+            KeymanagerMsg::SharedAliasChanged(e) => {
+                self.shared_alias = e.target_unchecked_into::<HtmlInputElement>().value();
+                true
+            }
+            KeymanagerMsg::AcceptShared => {
+                let shared = match self.pending_share.take() {
+                    Some(Ok(shared)) => shared,
+                    _ => return false,
+                };
+                let alias = self.shared_alias.trim().to_string();
+
+                let mut state = State::empty();
+                state.room = shared.room;
+                let imported = |participant: &str| {
+                    shared
+                        .pubkeys
+                        .iter()
+                        .find(|(a, _)| a == participant)
+                        .and_then(|(_, pubkey)| keys::parse_remote(pubkey).ok())
+                };
+                for participant in shared.participants.iter().filter(|p| **p != alias) {
+                    match imported(participant) {
+                        Some(key) => state.add_remote(participant.clone(), key),
+                        None => state.add_alias(participant.clone()),
+                    }
+                }
+                state.set_local(alias.clone());
+                // Only the public key is known: the secret has to be imported again
+                if let Some(key) = imported(&alias) {
+                    state.set_local_key(key);
+                }
+
+                let mut workspace = shared.workspace;
+                if let Some(pubkey) = state.local_pubkey() {
+                    share::key_to_my_key(&mut workspace, &pubkey);
+                }
+
+                // Shared wallets are opened as a new project, to avoid overwriting the current one
+                self.save_project();
+                self.projects
+                    .add(Project::new("Shared wallet".to_string(), state, workspace));
+                self.open_project();
+
+                share::clear_fragment();
+                self.shared_alias = String::new();
+
+                true
+            }
+            KeymanagerMsg::DismissShared => {
+                self.pending_share = None;
+                share::clear_fragment();
+                true
+            }
+
+            KeymanagerMsg::WorkspaceChanged(workspace) => {
+                let local_key = self
+                    .state
+                    .borrow()
+                    .local_key
+                    .as_ref()
+                    .and_then(|(k, _)| k.secret());
+                let policy = workspace.and_then(|workspace| {
+                    policy_blocks::workspace_to_policy(&workspace, local_key.as_deref())
+                        .map(|policy| keys::policy_with_origins(&policy))
+                        .map_err(|e| e.to_string())
+                });
+                log::info!("{:?}", policy);
+
+                // An empty policy clears the current wallet
+                let policy_str = policy.as_ref().map(|p| p.to_string()).unwrap_or_default();
+                self.policy_error = policy.err();
+                // Most events (selecting or dragging a block) don't change the policy
+                if policy_str != self.last_policy {
+                    self.bus.send(AppEvent::PolicyChanged(policy_str.clone()));
+                    self.last_policy = policy_str;
+                }
+
+                // Selecting a block doesn't change the saved workspace
+                let workspace = self.workspace.as_ref().map(export_workspace);
+                if workspace.is_some_and(|w| w != self.projects.current().workspace) {
+                    self.save_project();
+                }
+                true
+            }
+
+            KeymanagerMsg::ProjectSelected(e) => {
+                let value = e.target_unchecked_into::<HtmlSelectElement>().value();
+                match value.parse::<usize>() {
+                    Ok(i) if i < self.projects.list.len() && i != self.projects.current => {
+                        self.save_project();
+                        self.projects.current = i;
+                        self.open_project();
+                        true
+                    }
+                    _ => false,
+                }
+            }
+            KeymanagerMsg::NewProject => {
+                self.save_project();
+                self.projects.add(Project::new(
+                    "New wallet".to_string(),
+                    State::new(),
+                    projects::empty_workspace(),
+                ));
+                self.open_project();
+                true
+            }
+            KeymanagerMsg::DuplicateProject => {
+                self.save_project();
+                self.projects.duplicate_current();
+                self.open_project();
+                true
+            }
+            KeymanagerMsg::DeleteProject => {
+                self.projects.remove_current();
+                self.open_project();
+                true
+            }
+            KeymanagerMsg::ProjectRenamed(e) => {
+                self.projects.current_mut().name =
+                    e.target_unchecked_into::<HtmlInputElement>().value();
+                self.project_export = None;
+                self.save_project();
+                true
+            }
+            KeymanagerMsg::ProjectDescriptionChanged(e) => {
+                self.projects.current_mut().description =
+                    e.target_unchecked_into::<HtmlInputElement>().value();
+                self.save_project();
+                false
+            }
+            KeymanagerMsg::EsploraUrlChanged(e) => {
+                let url = e.target_unchecked_into::<HtmlInputElement>().value();
+                let settings = &mut self.projects.current_mut().settings;
+                settings.esplora_url = url.trim().trim_end_matches('/').to_string();
+                let settings = settings.clone();
+                self.save_project();
+
+                // Reload the wallet with the new backend
+                self.bus.send(AppEvent::SettingsChanged(settings));
+                true
+            }
+            KeymanagerMsg::VersionLabelChanged(e) => {
+                self.version_label = e.target_unchecked_into::<HtmlInputElement>().value();
+                true
+            }
+            KeymanagerMsg::SaveVersion => {
+                self.save_project();
+                let project = self.projects.current_mut();
+                let label = match self.version_label.trim() {
+                    "" => format!("Version {}", project.versions.len() + 1),
+                    label => label.to_string(),
+                };
+                project.snapshot(label);
+                self.version_label = String::new();
+                self.persist();
+                true
+            }
+            KeymanagerMsg::RestoreVersion(i) => {
+                self.projects.current_mut().restore(i);
+                self.open_project();
+                true
+            }
+            KeymanagerMsg::ExportProject => {
+                self.save_project();
+                let json = self.projects.current().to_json();
+                self.project_export = Some(ObjectUrl::from(Blob::new(json.as_str())));
+                true
+            }
+            KeymanagerMsg::ProjectFilesSelected(files) => {
+                for file in files {
+                    ctx.link().send_future(async move {
+                        let res = gloo_file::futures::read_as_text(&file)
+                            .await
+                            .map_err(|e| e.to_string())
+                            .and_then(|json| Project::from_json(&json))
+                            .map(Box::new)
+                            .map_err(|e| format!("{}: {}", file.name(), e));
+                        KeymanagerMsg::ProjectImported(res)
+                    });
+                }
+                false
+            }
+            KeymanagerMsg::PassphraseChanged(e) => {
+                self.passphrase = e.target_unchecked_into::<HtmlInputElement>().value();
+                self.passphrase_error = None;
+                true
+            }
+            KeymanagerMsg::PassphraseConfirmChanged(e) => {
+                self.passphrase_confirm = e.target_unchecked_into::<HtmlInputElement>().value();
+                self.passphrase_error = None;
+                true
+            }
+            KeymanagerMsg::SetPassphrase => {
+                if self.passphrase != self.passphrase_confirm {
+                    self.passphrase_error = Some("The passphrases don't match".to_string());
+                    return true;
+                }
+
+                encryption::start_session(encryption::EncryptionKey::new(&self.passphrase));
+                self.is_encrypted = true;
+                self.passphrase = String::new();
+                self.passphrase_confirm = String::new();
+                self.save_project();
+                true
+            }
+            KeymanagerMsg::RemovePassphrase => {
+                encryption::end_session();
+                self.is_encrypted = false;
+                self.save_project();
+                true
+            }
+            KeymanagerMsg::Lock => {
+                self.save_project();
+                self.lock();
+                true
+            }
+            KeymanagerMsg::Unlock => {
+                let locked = match &self.locked {
+                    Some(locked) => locked,
+                    None => return false,
+                };
+                match storage::unlock_projects(locked, &self.passphrase) {
+                    Ok((projects, key)) => {
+                        encryption::start_session(key);
+                        self.locked = None;
+                        self.projects = projects;
+                        self.passphrase = String::new();
+                        self.open_project();
+                    }
+                    Err(e) => self.passphrase_error = Some(e),
+                }
+                true
+            }
+            KeymanagerMsg::Locked => {
+                if self.is_encrypted && self.locked.is_none() {
+                    self.forget();
+                    true
+                } else {
+                    false
+                }
+            }
+            KeymanagerMsg::DismissStorageError => {
+                self.storage_error = None;
+                true
+            }
+            KeymanagerMsg::ProjectImported(res) => {
+                match res {
+                    Ok(project) => {
+                        self.save_project();
+                        self.projects.add(*project);
+                        self.open_project();
+                    }
+                    Err(e) => self.project_error = Some(e),
                 }
-                log::info!("{}", desc);
-                self.dispatcher.send(Request::EventBusMsg(desc));
                 true
             }
         }
@@ -473,8 +1705,6 @@ impl Component for Keymanager {
     }
 
     fn destroy(&mut self, _ctx: &Context<Self>) {
-        if let Some(workspace) = &self.workspace {
-            save_blockly(workspace);
-        }
+        self.save_project();
     }
 }
@@ -0,0 +1,600 @@
+use std::fmt;
+
+use bdk::bitcoin::hashes::hex::ToHex;
+use bdk::miniscript::policy::Concrete;
+use serde_json::{json, Value};
+
+use super::blockly::{SerializedBlock, SerializedWorkspace};
+use super::State;
+use crate::hashlock::HashKind;
+use crate::timelock::{self, TimeUnit};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum PolicyBuildError {
+    /// There's no `begin` block in the workspace
+    MissingBegin,
+    /// Nothing is attached to the `begin` block
+    EmptyPolicy,
+    /// A block has nothing attached to one of its inputs
+    EmptyInput {
+        block: String,
+        input: &'static str,
+    },
+    /// Blocks that aren't connected to the `begin` block
+    OrphanedBlocks(usize),
+    /// More than one statement is chained outside of a threshold
+    UnexpectedStatement(String),
+    /// A field is missing or contains an invalid value
+    InvalidField {
+        block: String,
+        field: &'static str,
+    },
+    /// A timelock is out of range or can't be parsed
+    InvalidTimelock {
+        block: String,
+        reason: String,
+    },
+    /// The threshold is zero or greater than the number of sub-policies
+    InvalidThreshold {
+        threshold: u32,
+        n: usize,
+    },
+    /// A `my_key` block is used, but no local key has been set
+    MissingLocalKey,
+    UnknownBlock(String),
+}
+
+impl fmt::Display for PolicyBuildError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            PolicyBuildError::MissingBegin => write!(f, "The `Begin` block is missing"),
+            PolicyBuildError::EmptyPolicy => write!(f, "Attach a policy to the `Begin` block"),
+            PolicyBuildError::EmptyInput { block, input } => {
+                write!(
+                    f,
+                    "The `{}` block has nothing attached to `{}`",
+                    block, input
+                )
+            }
+            PolicyBuildError::OrphanedBlocks(n) => write!(
+                f,
+                "{} block(s) are not connected to the `Begin` block, remove them or attach them",
+                n
+            ),
+            PolicyBuildError::UnexpectedStatement(block) => write!(
+                f,
+                "The `{}` block can only be chained to other blocks inside a threshold",
+                block
+            ),
+            PolicyBuildError::InvalidField { block, field } => {
+                write!(f, "The `{}` block has an invalid `{}`", block, field)
+            }
+            PolicyBuildError::InvalidTimelock { block, reason } => {
+                write!(f, "Invalid timelock in the `{}` block: {}", block, reason)
+            }
+            PolicyBuildError::InvalidThreshold { threshold, n } => write!(
+                f,
+                "Invalid threshold {} of {}: it must be between 1 and the number of sub-policies",
+                threshold, n
+            ),
+            PolicyBuildError::MissingLocalKey => {
+                write!(f, "Set your local key before using the `My Key` block")
+            }
+            PolicyBuildError::UnknownBlock(ty) => write!(f, "Unknown block `{}`", ty),
+        }
+    }
+}
+
+impl std::error::Error for PolicyBuildError {}
+
+/// Builds the policy described by a serialized Blockly workspace. `my_key` blocks are replaced
+/// by `local_key`, the secret key in descriptor format.
+pub fn workspace_to_policy(
+    workspace: &SerializedWorkspace,
+    local_key: Option<&str>,
+) -> Result<Concrete<String>, PolicyBuildError> {
+    let top_blocks = &workspace.blocks.blocks;
+    let begin = top_blocks
+        .iter()
+        .find(|b| b.ty == "begin")
+        .ok_or(PolicyBuildError::MissingBegin)?;
+    if top_blocks.len() > 1 {
+        return Err(PolicyBuildError::OrphanedBlocks(top_blocks.len() - 1));
+    }
+
+    let root = begin.next().ok_or(PolicyBuildError::EmptyPolicy)?;
+    if root.next().is_some() {
+        return Err(PolicyBuildError::UnexpectedStatement(root.ty.clone()));
+    }
+
+    block_to_policy(root, local_key)
+}
+
+fn block_to_policy(
+    block: &SerializedBlock,
+    local_key: Option<&str>,
+) -> Result<Concrete<String>, PolicyBuildError> {
+    let input = |name: &'static str| {
+        block.input(name).ok_or(PolicyBuildError::EmptyInput {
+            block: block.ty.clone(),
+            input: name,
+        })
+    };
+    let field = |name: &'static str| {
+        block.field_u32(name).ok_or(PolicyBuildError::InvalidField {
+            block: block.ty.clone(),
+            field: name,
+        })
+    };
+    let timelock = |res: Result<u32, String>| {
+        res.map_err(|reason| PolicyBuildError::InvalidTimelock {
+            block: block.ty.clone(),
+            reason,
+        })
+    };
+    let statement = |name: &'static str| {
+        let sub = input(name)?;
+        if sub.next().is_some() {
+            return Err(PolicyBuildError::UnexpectedStatement(sub.ty.clone()));
+        }
+        block_to_policy(sub, local_key)
+    };
+
+    Ok(match block.ty.as_str() {
+        "pk" => Concrete::Key(key_to_string(input("Key")?, local_key)?),
+        "older" => Concrete::older(timelock(timelock::older_blocks(field("value")?))?),
+        "older_time" => {
+            let unit = block
+                .field_str("Unit")
+                .and_then(TimeUnit::from_name)
+                .ok_or(PolicyBuildError::InvalidField {
+                    block: block.ty.clone(),
+                    field: "Unit",
+                })?;
+            Concrete::older(timelock(timelock::older_time(field("value")?, unit))?)
+        }
+        "after" => Concrete::after(timelock(timelock::after_height(field("value")?))?),
+        "after_time" => Concrete::after(timelock(timelock::after_time(
+            block.field_str("Date").unwrap_or_default(),
+        ))?),
+        "and" => Concrete::And(vec![statement("A")?, statement("B")?]),
+        "or" => Concrete::Or(vec![
+            (field("A_weight")? as usize, statement("A")?),
+            (field("B_weight")? as usize, statement("B")?),
+        ]),
+        "thresh" => {
+            let threshold = field("Threshold")?;
+            let mut subs = vec![];
+            let mut next = Some(input("Statements")?);
+            while let Some(sub) = next {
+                subs.push(block_to_policy(sub, local_key)?);
+                next = sub.next();
+            }
+            if threshold == 0 || threshold as usize > subs.len() {
+                return Err(PolicyBuildError::InvalidThreshold {
+                    threshold,
+                    n: subs.len(),
+                });
+            }
+            Concrete::Threshold(threshold as usize, subs)
+        }
+        ty => match HashKind::from_name(ty) {
+            Some(kind) => hash_lock_policy(block, kind)?,
+            None => return Err(PolicyBuildError::UnknownBlock(ty.to_string())),
+        },
+    })
+}
+
+/// Hash-lock blocks contain either the hash itself, or a secret that we hash here
+fn hash_lock_policy(
+    block: &SerializedBlock,
+    kind: HashKind,
+) -> Result<Concrete<String>, PolicyBuildError> {
+    let invalid_value = PolicyBuildError::InvalidField {
+        block: block.ty.clone(),
+        field: "Value",
+    };
+    let value = block.field_str("Value").unwrap_or_default();
+    let digest = match block.field_str("Mode") {
+        Some("secret") if !value.is_empty() => kind.digest_of_secret(value),
+        Some("secret") => return Err(invalid_value),
+        _ => kind
+            .parse_digest(value)
+            .map(|d| d.to_hex())
+            .ok_or(invalid_value)?,
+    };
+
+    Ok(match kind {
+        HashKind::Sha256 => Concrete::Sha256(digest),
+        HashKind::Hash256 => Concrete::Hash256(digest),
+        HashKind::Ripemd160 => Concrete::Ripemd160(digest),
+        HashKind::Hash160 => Concrete::Hash160(digest),
+    })
+}
+
+fn key_to_string(
+    block: &SerializedBlock,
+    local_key: Option<&str>,
+) -> Result<String, PolicyBuildError> {
+    match block.ty.as_str() {
+        "my_key" => local_key
+            .map(str::to_string)
+            .ok_or(PolicyBuildError::MissingLocalKey),
+        "key" => block
+            .field_str("Key")
+            .filter(|k| !k.is_empty())
+            .map(str::to_string)
+            .ok_or(PolicyBuildError::InvalidField {
+                block: block.ty.clone(),
+                field: "Key",
+            }),
+        ty => Err(PolicyBuildError::UnknownBlock(ty.to_string())),
+    }
+}
+
+/// Builds a serialized Blockly workspace representing `policy`, attached to the `begin` block.
+///
+/// Keys can be aliases, WIFs or public keys: they are matched against the keys in `state` to
+/// decide between `my_key` and `key` blocks.
+pub fn policy_to_workspace(policy: &Concrete<String>, state: &State) -> Result<Value, String> {
+    let root = policy_block(policy, state)?;
+
+    Ok(json!({
+        "blocks": {
+            "languageVersion": 0,
+            "blocks": [{
+                "type": "begin",
+                "x": 20,
+                "y": 20,
+                "deletable": false,
+                "editable": false,
+                "next": { "block": root },
+            }],
+        }
+    }))
+}
+
+fn policy_block(policy: &Concrete<String>, state: &State) -> Result<Value, String> {
+    Ok(match policy {
+        Concrete::Key(key) => json!({
+            "type": "pk",
+            "inputs": { "Key": { "block": key_block(key, state)? } },
+        }),
+        Concrete::Older(sequence) if timelock::is_older_time(sequence.to_consensus_u32()) => {
+            let (value, unit) = timelock::older_time_to_duration(sequence.to_consensus_u32());
+            json!({
+                "type": "older_time",
+                "fields": { "value": value, "Unit": unit.name() },
+            })
+        }
+        Concrete::Older(sequence) => json!({
+            "type": "older",
+            "fields": { "value": sequence.to_consensus_u32() },
+        }),
+        Concrete::After(locktime) if locktime.0 >= timelock::LOCKTIME_THRESHOLD => json!({
+            "type": "after_time",
+            "fields": { "Date": timelock::format_timestamp(locktime.0) },
+        }),
+        Concrete::After(locktime) => json!({
+            "type": "after",
+            "fields": { "value": locktime.0 },
+        }),
+        Concrete::And(subs) if subs.len() == 2 => json!({
+            "type": "and",
+            "inputs": {
+                "A": { "block": policy_block(&subs[0], state)? },
+                "B": { "block": policy_block(&subs[1], state)? },
+            },
+        }),
+        Concrete::Or(subs) if subs.len() == 2 => json!({
+            "type": "or",
+            "fields": { "A_weight": subs[0].0, "B_weight": subs[1].0 },
+            "inputs": {
+                "A": { "block": policy_block(&subs[0].1, state)? },
+                "B": { "block": policy_block(&subs[1].1, state)? },
+            },
+        }),
+        Concrete::Threshold(k, subs) => {
+            // Statements inside a threshold are chained through their `next` connection
+            let mut chain: Option<Value> = None;
+            for sub in subs.iter().rev() {
+                let mut block = policy_block(sub, state)?;
+                if let Some(next) = chain.take() {
+                    block["next"] = json!({ "block": next });
+                }
+                chain = Some(block);
+            }
+
+            let mut block = json!({
+                "type": "thresh",
+                "fields": { "Threshold": k },
+            });
+            if let Some(first) = chain {
+                block["inputs"] = json!({ "Statements": { "block": first } });
+            }
+            block
+        }
+        Concrete::Sha256(hash) => hash_lock_block(HashKind::Sha256, hash),
+        Concrete::Hash256(hash) => hash_lock_block(HashKind::Hash256, hash),
+        Concrete::Ripemd160(hash) => hash_lock_block(HashKind::Ripemd160, hash),
+        Concrete::Hash160(hash) => hash_lock_block(HashKind::Hash160, hash),
+        Concrete::And(_) | Concrete::Or(_) => {
+            return Err("`and` and `or` must have exactly two sub-policies".to_string())
+        }
+        _ => return Err(format!("`{}` can't be represented with blocks", policy)),
+    })
+}
+
+fn hash_lock_block(kind: HashKind, hash: &str) -> Value {
+    json!({
+        "type": kind.name(),
+        "fields": { "Mode": "hash", "Value": hash },
+    })
+}
+
+fn key_block(key: &str, state: &State) -> Result<Value, String> {
+    if let Some((local, alias)) = &state.local_key {
+        if key == alias || local.matches(key) {
+            return Ok(json!({ "type": "my_key" }));
+        }
+    }
+
+    state
+        .keys
+        .iter()
+        .find(|(alias, remote)| key == alias || remote.matches(key))
+        .map(|(_, remote)| json!({ "type": "key", "fields": { "Key": remote.public() } }))
+        .ok_or_else(|| format!("`{}` is not in the key manager", key))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::str::FromStr;
+
+    use super::*;
+
+    /// Alice, Bob and Carol, with Alice as the local key
+    fn state() -> State {
+        let mut state = State::empty();
+        for alias in ["Bob", "Carol"] {
+            state.add_alias(alias.to_string());
+        }
+        state.set_local("Alice".to_string());
+        state
+    }
+
+    fn pubkey(state: &State, alias: &str) -> String {
+        match alias {
+            "Alice" => state.local_pubkey().unwrap(),
+            alias => state
+                .keys
+                .iter()
+                .find(|(a, _)| a == alias)
+                .unwrap()
+                .1
+                .public(),
+        }
+    }
+
+    /// Replaces the aliases in `policy` with their public keys, and Alice's with her secret key
+    fn policy(state: &State, policy: &str) -> Concrete<String> {
+        let local = state.local_key.as_ref().unwrap().0.secret().unwrap();
+        let policy = policy
+            .replace("Alice", &local)
+            .replace("Bob", &pubkey(state, "Bob"))
+            .replace("Carol", &pubkey(state, "Carol"));
+        Concrete::from_str(&policy).unwrap()
+    }
+
+    fn round_trip(state: &State, policy: &Concrete<String>) -> Concrete<String> {
+        let workspace = policy_to_workspace(policy, state).unwrap();
+        let workspace = serde_json::from_value(workspace).unwrap();
+        let local = state.local_key.as_ref().and_then(|(k, _)| k.secret());
+        workspace_to_policy(&workspace, local.as_deref()).unwrap()
+    }
+
+    #[test]
+    fn policies_round_trip() {
+        let state = state();
+        let sha256 = "a".repeat(64);
+        let ripemd160 = "b".repeat(40);
+        for p in [
+            "pk(Bob)".to_string(),
+            "pk(Alice)".to_string(),
+            "and(pk(Alice),pk(Bob))".to_string(),
+            "or(3@pk(Alice),1@and(pk(Bob),older(144)))".to_string(),
+            "thresh(2,pk(Alice),pk(Bob),pk(Carol))".to_string(),
+            "thresh(1,pk(Bob),and(pk(Carol),after(700000)))".to_string(),
+            "and(pk(Bob),older(4194312))".to_string(),
+            "and(pk(Bob),after(1577836800))".to_string(),
+            "and(pk(Bob),after(1709209815))".to_string(),
+            format!("and(pk(Bob),sha256({}))", sha256),
+            format!("or(pk(Carol),and(pk(Bob),ripemd160({})))", ripemd160),
+            format!("and(hash256({}),hash160({}))", sha256, ripemd160),
+        ] {
+            let policy = policy(&state, &p);
+            assert_eq!(round_trip(&state, &policy), policy, "{}", p);
+        }
+    }
+
+    #[test]
+    fn time_blocks() {
+        let state = state();
+        let workspace = policy_to_workspace(&policy(&state, "older(4194312)"), &state).unwrap();
+        let older = &workspace["blocks"]["blocks"][0]["next"]["block"];
+        assert_eq!(older["type"], "older_time");
+        assert_eq!(older["fields"], json!({ "value": 1, "Unit": "hours" }));
+
+        let workspace = policy_to_workspace(&policy(&state, "after(1577836800)"), &state).unwrap();
+        let after = &workspace["blocks"]["blocks"][0]["next"]["block"];
+        assert_eq!(after["type"], "after_time");
+        assert_eq!(after["fields"]["Date"], "2020-01-01 00:00");
+    }
+
+    #[test]
+    fn policies_without_blocks() {
+        let state = state();
+        let bob = Concrete::Key(pubkey(&state, "Bob"));
+        let and3 = Concrete::And(vec![bob.clone(), bob.clone(), bob]);
+        assert!(policy_to_workspace(&and3, &state)
+            .unwrap_err()
+            .contains("exactly two sub-policies"));
+
+        let mut others = State::empty();
+        others.add_alias("Dave".to_string());
+        let unknown = Concrete::Key(pubkey(&others, "Dave"));
+        assert!(policy_to_workspace(&unknown, &state)
+            .unwrap_err()
+            .contains("is not in the key manager"));
+    }
+
+    const BOB: &str = "02e6642fd69bd211f93f7f1f36ca51a26a5290eb2dd1b0d8279a87bb0d480c8443";
+
+    /// Builds the policy of a workspace with the given top blocks
+    fn build(blocks: Value, local_key: Option<&str>) -> Result<Concrete<String>, PolicyBuildError> {
+        let workspace = json!({ "blocks": { "languageVersion": 0, "blocks": blocks } });
+        workspace_to_policy(&serde_json::from_value(workspace).unwrap(), local_key)
+    }
+
+    fn begin(root: Value) -> Value {
+        json!([{ "type": "begin", "next": { "block": root } }])
+    }
+
+    fn pk(key: Value) -> Value {
+        json!({ "type": "pk", "inputs": { "Key": { "block": key } } })
+    }
+
+    fn bob() -> Value {
+        pk(json!({ "type": "key", "fields": { "Key": BOB } }))
+    }
+
+    fn thresh(threshold: Value, statements: Value) -> Value {
+        json!({
+            "type": "thresh",
+            "fields": { "Threshold": threshold },
+            "inputs": { "Statements": { "block": statements } },
+        })
+    }
+
+    #[test]
+    fn workspace_errors() {
+        assert_eq!(build(json!([]), None), Err(PolicyBuildError::MissingBegin));
+        assert_eq!(
+            build(json!([bob()]), None),
+            Err(PolicyBuildError::MissingBegin)
+        );
+        assert_eq!(
+            build(json!([{ "type": "begin" }]), None),
+            Err(PolicyBuildError::EmptyPolicy)
+        );
+
+        let mut blocks = begin(bob());
+        blocks.as_array_mut().unwrap().extend([bob(), bob()]);
+        assert_eq!(
+            build(blocks, None),
+            Err(PolicyBuildError::OrphanedBlocks(2))
+        );
+
+        let mut chained = bob();
+        chained["next"] = json!({ "block": bob() });
+        assert_eq!(
+            build(begin(chained.clone()), None),
+            Err(PolicyBuildError::UnexpectedStatement("pk".to_string()))
+        );
+        let and = json!({
+            "type": "and",
+            "inputs": { "A": { "block": chained }, "B": { "block": bob() } },
+        });
+        assert_eq!(
+            build(begin(and), None),
+            Err(PolicyBuildError::UnexpectedStatement("pk".to_string()))
+        );
+    }
+
+    #[test]
+    fn block_errors() {
+        let and = json!({ "type": "and", "inputs": { "A": { "block": bob() } } });
+        assert_eq!(
+            build(begin(and), None),
+            Err(PolicyBuildError::EmptyInput {
+                block: "and".to_string(),
+                input: "B"
+            })
+        );
+        assert_eq!(
+            build(begin(json!({ "type": "pk" })), None),
+            Err(PolicyBuildError::EmptyInput {
+                block: "pk".to_string(),
+                input: "Key"
+            })
+        );
+
+        let mut chain = bob();
+        chain["next"] = json!({ "block": bob() });
+        assert_eq!(
+            build(begin(thresh(json!(0), chain.clone())), None),
+            Err(PolicyBuildError::InvalidThreshold { threshold: 0, n: 2 })
+        );
+        assert_eq!(
+            build(begin(thresh(json!(3), chain.clone())), None),
+            Err(PolicyBuildError::InvalidThreshold { threshold: 3, n: 2 })
+        );
+        assert_eq!(
+            build(begin(thresh(json!("two"), chain)), None),
+            Err(PolicyBuildError::InvalidField {
+                block: "thresh".to_string(),
+                field: "Threshold"
+            })
+        );
+
+        let mine = pk(json!({ "type": "my_key" }));
+        assert_eq!(
+            build(begin(mine.clone()), None),
+            Err(PolicyBuildError::MissingLocalKey)
+        );
+        assert_eq!(
+            build(begin(mine), Some("local")),
+            Ok(Concrete::Key("local".to_string()))
+        );
+
+        let older = json!({ "type": "older", "fields": { "value": 0 } });
+        assert!(matches!(
+            build(begin(older), None),
+            Err(PolicyBuildError::InvalidTimelock { .. })
+        ));
+        let older = json!({ "type": "older_time", "fields": { "value": 1, "Unit": "years" } });
+        assert_eq!(
+            build(begin(older), None),
+            Err(PolicyBuildError::InvalidField {
+                block: "older_time".to_string(),
+                field: "Unit"
+            })
+        );
+        let sha256 = json!({ "type": "sha256", "fields": { "Mode": "hash", "Value": "00" } });
+        assert_eq!(
+            build(begin(sha256), None),
+            Err(PolicyBuildError::InvalidField {
+                block: "sha256".to_string(),
+                field: "Value"
+            })
+        );
+        assert_eq!(
+            build(begin(json!({ "type": "unknown" })), None),
+            Err(PolicyBuildError::UnknownBlock("unknown".to_string()))
+        );
+    }
+
+    #[test]
+    fn legacy_string_numbers() {
+        let mut chain = bob();
+        chain["next"] =
+            json!({ "block": json!({ "type": "older", "fields": { "value": "144" } }) });
+        assert_eq!(
+            build(begin(thresh(json!("2"), chain)), None),
+            Ok(Concrete::Threshold(
+                2,
+                vec![Concrete::Key(BOB.to_string()), Concrete::older(144)]
+            ))
+        );
+    }
+}
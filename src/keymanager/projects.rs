@@ -0,0 +1,170 @@
+use serde::{Deserialize, Serialize};
+use serde_json::{json, Value};
+
+use super::{share, State};
+use crate::app_wallet::BackendSettings;
+use crate::timelock;
+
+/// A named snapshot of the keys and blocks of a project
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ProjectVersion {
+    pub label: String,
+    pub created: f64,
+    pub state: State,
+    pub workspace: Value,
+}
+
+/// Everything needed to recreate a wallet design: keys, blocks and backend
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Project {
+    pub name: String,
+    #[serde(default)]
+    pub description: String,
+    /// Milliseconds since the UNIX epoch
+    pub created: f64,
+    pub modified: f64,
+    #[serde(default)]
+    pub settings: BackendSettings,
+    pub state: State,
+    pub workspace: Value,
+    #[serde(default)]
+    pub versions: Vec<ProjectVersion>,
+}
+
+impl Project {
+    pub fn new(name: String, state: State, workspace: Value) -> Self {
+        let now = js_sys::Date::now();
+        Project {
+            name,
+            description: String::new(),
+            created: now,
+            modified: now,
+            settings: BackendSettings::default(),
+            state,
+            workspace,
+            versions: vec![],
+        }
+    }
+
+    pub fn snapshot(&mut self, label: String) {
+        self.versions.push(ProjectVersion {
+            label,
+            created: js_sys::Date::now(),
+            state: self.state.clone(),
+            workspace: self.workspace.clone(),
+        });
+    }
+
+    /// Replaces the keys and blocks with the ones of a version. Versions are kept.
+    pub fn restore(&mut self, version: usize) {
+        if let Some(version) = self.versions.get(version) {
+            self.state = version.state.clone();
+            self.workspace = version.workspace.clone();
+            self.modified = js_sys::Date::now();
+        }
+    }
+
+    /// Project file, with the secrets of hash-lock blocks replaced by their digests
+    pub fn to_json(&self) -> String {
+        let mut project = self.clone();
+        share::hash_secrets(&mut project.workspace);
+        for version in &mut project.versions {
+            share::hash_secrets(&mut version.workspace);
+        }
+        serde_json::to_string_pretty(&project).unwrap()
+    }
+
+    pub fn from_json(json: &str) -> Result<Self, String> {
+        serde_json::from_str(json).map_err(|e| format!("Not a valid project file: {}", e))
+    }
+
+    pub fn file_name(&self) -> String {
+        let name = self
+            .name
+            .chars()
+            .map(|c| if c.is_alphanumeric() { c } else { '-' })
+            .collect::<String>();
+        format!("{}.elephant.json", name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Projects {
+    pub current: usize,
+    pub list: Vec<Project>,
+}
+
+impl Projects {
+    pub fn new(project: Project) -> Self {
+        Projects {
+            current: 0,
+            list: vec![project],
+        }
+    }
+
+    pub fn current(&self) -> &Project {
+        &self.list[self.current]
+    }
+
+    pub fn current_mut(&mut self) -> &mut Project {
+        &mut self.list[self.current]
+    }
+
+    /// Adds a project, renaming it if the name is already taken, and opens it
+    pub fn add(&mut self, mut project: Project) {
+        project.name = self.unique_name(&project.name);
+        self.list.push(project);
+        self.current = self.list.len() - 1;
+    }
+
+    pub fn duplicate_current(&mut self) {
+        let mut project = self.current().clone();
+        let now = js_sys::Date::now();
+        project.created = now;
+        project.modified = now;
+        self.add(project);
+    }
+
+    /// Removes the current project, unless it's the last one
+    pub fn remove_current(&mut self) {
+        if self.list.len() > 1 {
+            self.list.remove(self.current);
+            self.current = self.current.min(self.list.len() - 1);
+        }
+    }
+
+    fn unique_name(&self, name: &str) -> String {
+        let taken = |n: &str| self.list.iter().any(|p| p.name == n);
+        if !taken(name) {
+            return name.to_string();
+        }
+
+        (2..)
+            .map(|i| format!("{} ({})", name, i))
+            .find(|n| !taken(n))
+            .unwrap()
+    }
+}
+
+/// A workspace containing only the `begin` block
+pub fn empty_workspace() -> Value {
+    json!({
+        "blocks": {
+            "languageVersion": 0,
+            "blocks": [{
+                "type": "begin",
+                "x": 20,
+                "y": 20,
+                "deletable": false,
+                "editable": false,
+            }],
+        }
+    })
+}
+
+pub fn format_date(millis: f64) -> String {
+    format!(
+        "{} UTC",
+        timelock::format_timestamp((millis / 1000.0) as u32)
+    )
+}
@@ -0,0 +1,164 @@
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+
+use crate::hashlock::HashKind;
+
+/// Prefix of the URL fragment carrying a shared wallet
+const SHARE_FRAGMENT: &str = "#/share?data=";
+
+/// Everything needed to recreate a wallet design in another browser
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct SharedWallet {
+    /// Aliases of everyone taking part in the wallet, including whoever created the link
+    pub participants: Vec<String>,
+    /// Room code used to derive the keys of the participants
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub room: Option<String>,
+    /// Public keys of the participants whose key isn't derived from their alias
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub pubkeys: Vec<(String, String)>,
+    /// Blockly workspace, with the creator's `my_key` blocks replaced by `key` blocks
+    pub workspace: Value,
+}
+
+impl SharedWallet {
+    pub fn to_link(&self, base_url: &str) -> String {
+        let json = serde_json::to_vec(self).expect("serializable");
+        format!(
+            "{}{}{}",
+            base_url,
+            SHARE_FRAGMENT,
+            base64::encode_config(json, base64::URL_SAFE_NO_PAD)
+        )
+    }
+
+    /// Parses a URL fragment, returning `None` if it doesn't contain a shared wallet
+    pub fn from_fragment(fragment: &str) -> Option<Result<Self, String>> {
+        let data = fragment.strip_prefix(SHARE_FRAGMENT)?;
+        Some(
+            base64::decode_config(data, base64::URL_SAFE_NO_PAD)
+                .map_err(|e| e.to_string())
+                .and_then(|json| serde_json::from_slice(&json).map_err(|e| e.to_string())),
+        )
+    }
+}
+
+/// URL of the current page, without the fragment
+pub fn base_url() -> String {
+    let location = web_sys::window().unwrap().location();
+    format!(
+        "{}{}",
+        location.origin().unwrap_or_default(),
+        location.pathname().unwrap_or_default()
+    )
+}
+
+pub fn current_fragment() -> String {
+    web_sys::window()
+        .unwrap()
+        .location()
+        .hash()
+        .unwrap_or_default()
+}
+
+/// Removes the shared wallet from the address bar, so that a reload doesn't import it again
+pub fn clear_fragment() {
+    let window = web_sys::window().unwrap();
+    if let Ok(history) = window.history() {
+        let _ = history.replace_state_with_url(&wasm_bindgen::JsValue::NULL, "", Some(&base_url()));
+    }
+}
+
+/// Whether the workspace contains at least one `my_key` block
+pub fn uses_my_key(workspace: &Value) -> bool {
+    any_block(workspace, &|block| block["type"] == "my_key")
+}
+
+/// Replaces every `my_key` block with a `key` block pointing to `pubkey`
+pub fn my_key_to_key(workspace: &mut Value, pubkey: &str) {
+    map_blocks(workspace, &mut |block| {
+        if block["type"] == "my_key" {
+            block["type"] = "key".into();
+            block["fields"] = serde_json::json!({ "Key": pubkey });
+        }
+    });
+}
+
+/// Replaces every `key` block pointing to `pubkey` with a `my_key` block
+pub fn key_to_my_key(workspace: &mut Value, pubkey: &str) {
+    map_blocks(workspace, &mut |block| {
+        if block["type"] == "key" && block["fields"]["Key"] == pubkey {
+            block["type"] = "my_key".into();
+            if let Some(block) = block.as_object_mut() {
+                block.remove("fields");
+            }
+        }
+    });
+}
+
+/// Replaces the secret of every hash-lock block in "secret" mode with its digest, so that the
+/// secret never leaves this browser
+pub fn hash_secrets(workspace: &mut Value) {
+    map_blocks(workspace, &mut |block| {
+        let kind = match block["type"].as_str().and_then(HashKind::from_name) {
+            Some(kind) => kind,
+            None => return,
+        };
+        if block["fields"]["Mode"] != "secret" {
+            return;
+        }
+        let digest = match block["fields"]["Value"].as_str() {
+            Some(secret) if !secret.is_empty() => kind.digest_of_secret(secret),
+            _ => String::new(),
+        };
+        block["fields"] = serde_json::json!({ "Mode": "hash", "Value": digest });
+    });
+}
+
+/// Points `key` blocks to new public keys, given as `(old, new)` pairs
+pub fn replace_keys(workspace: &mut Value, changes: &[(String, String)]) {
+    map_blocks(workspace, &mut |block| {
+        if block["type"] != "key" {
+            return;
+        }
+        if let Some((_, new)) = changes
+            .iter()
+            .find(|(old, _)| block["fields"]["Key"] == *old)
+        {
+            block["fields"]["Key"] = new.as_str().into();
+        }
+    });
+}
+
+/// Calls `f` on every block of a serialized Blockly workspace, recursively
+fn map_blocks(value: &mut Value, f: &mut impl FnMut(&mut Value)) {
+    match value {
+        Value::Object(map) => {
+            if map.contains_key("type") {
+                f(value);
+            }
+            if let Value::Object(map) = value {
+                for (_, child) in map.iter_mut() {
+                    map_blocks(child, f);
+                }
+            }
+        }
+        Value::Array(items) => {
+            for item in items {
+                map_blocks(item, f);
+            }
+        }
+        _ => {}
+    }
+}
+
+/// Whether `f` holds for any block of a serialized Blockly workspace
+fn any_block(value: &Value, f: &impl Fn(&Value) -> bool) -> bool {
+    match value {
+        Value::Object(map) => {
+            (map.contains_key("type") && f(value)) || map.values().any(|child| any_block(child, f))
+        }
+        Value::Array(items) => items.iter().any(|item| any_block(item, f)),
+        _ => false,
+    }
+}
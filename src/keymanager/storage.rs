@@ -1,13 +1,319 @@
+use std::fmt;
+
 use gloo_storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
 
+use super::encryption::{Encrypted, EncryptionKey};
+use super::projects::{Project, Projects};
 use super::State;
+use crate::app_wallet::BackendSettings;
 
 const STORAGE_KEY: &str = "KEYMAN_STATE";
+const PROJECTS_STORAGE_KEY: &str = "PROJECTS";
+const BACKUP_STORAGE_KEY_PREFIX: &str = "BACKUP_";
+
+/// Version of the data written by `save_projects`.
+///
+/// - 0: a bare `State` under `KEYMAN_STATE`, before projects were introduced
+/// - 1: `Projects` in an `Envelope` under `PROJECTS`, encrypted if a passphrase is set
+pub const SCHEMA_VERSION: u32 = 1;
+
+/// `MIGRATIONS[i]` upgrades the data from version `i` to version `i + 1`
+const MIGRATIONS: &[Migration] = &[migrate_0_to_1];
+
+type Migration = fn(Value) -> Result<Value, String>;
+
+#[derive(Serialize, Deserialize)]
+struct Envelope {
+    version: u32,
+    #[serde(default)]
+    data: Value,
+    /// Replaces `data` when a passphrase is set
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    encrypted: Option<Encrypted>,
+}
+
+/// Projects protected by a passphrase, they can be read with `unlock_projects`
+#[derive(Debug, Clone, PartialEq)]
+pub struct LockedProjects {
+    version: u32,
+    encrypted: Encrypted,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum StoredProjects {
+    Plain(Projects),
+    Locked(LockedProjects),
+}
+
+/// Persisted data that couldn't be loaded. The raw JSON is kept in LocalStorage under
+/// `backup_key` and nothing is discarded.
+#[derive(Debug, Clone, PartialEq)]
+pub struct StorageError {
+    pub reason: String,
+    pub raw: String,
+    pub backup_key: String,
+}
+
+impl fmt::Display for StorageError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "Your saved data couldn't be loaded ({}). A copy has been kept under `{}` in the browser storage",
+            self.reason, self.backup_key
+        )
+    }
+}
+
+impl std::error::Error for StorageError {}
+
+/// Saves the projects, encrypting them if a key is given
+pub fn save_projects(projects: &Projects, key: Option<&EncryptionKey>) {
+    let data = serde_json::to_value(projects).unwrap();
+    let envelope = match key {
+        Some(key) => Envelope {
+            version: SCHEMA_VERSION,
+            data: Value::Null,
+            encrypted: Some(key.encrypt(data.to_string().as_bytes())),
+        },
+        None => Envelope {
+            version: SCHEMA_VERSION,
+            data,
+            encrypted: None,
+        },
+    };
+    if let Err(e) = LocalStorage::set(PROJECTS_STORAGE_KEY, envelope) {
+        log::warn!("Failed to save the projects: {}", e);
+        return;
+    }
+
+    if key.is_some() {
+        // Don't leave the keys in clear text in the data saved before projects existed
+        LocalStorage::delete(STORAGE_KEY);
+    }
+}
+
+/// Loads the projects, migrating the state saved before projects were introduced if there's no
+/// project yet
+pub fn load_projects() -> Result<Option<StoredProjects>, StorageError> {
+    if let Some(raw) = load_raw(PROJECTS_STORAGE_KEY) {
+        return parse_projects(&raw)
+            .map(Some)
+            .map_err(|reason| preserve(PROJECTS_STORAGE_KEY, raw, reason));
+    }
+
+    match load_raw(STORAGE_KEY) {
+        Some(raw) => parse_state(&raw)
+            .map(|projects| Some(StoredProjects::Plain(projects)))
+            .map_err(|reason| preserve(STORAGE_KEY, raw, reason)),
+        None => Ok(None),
+    }
+}
+
+/// Decrypts the projects, returning the key to use for the following saves
+pub fn unlock_projects(
+    locked: &LockedProjects,
+    passphrase: &str,
+) -> Result<(Projects, EncryptionKey), String> {
+    let (key, plaintext) = EncryptionKey::unlock(passphrase, &locked.encrypted)?;
+    let data = serde_json::from_slice(&plaintext).map_err(|e| e.to_string())?;
+    let projects = migrate(locked.version, data)?;
+
+    Ok((projects, key))
+}
+
+/// Decrypts the projects with the key of the current session
+pub fn unlock_projects_with_key(
+    locked: &LockedProjects,
+    key: &EncryptionKey,
+) -> Result<Projects, String> {
+    let plaintext = key.decrypt(&locked.encrypted)?;
+    let data = serde_json::from_slice(&plaintext).map_err(|e| e.to_string())?;
+
+    migrate(locked.version, data)
+}
 
-pub fn save(state: &State) {
-    LocalStorage::set(STORAGE_KEY, state).unwrap()
+fn parse_projects(raw: &str) -> Result<StoredProjects, String> {
+    let envelope = serde_json::from_str::<Envelope>(raw).map_err(|e| e.to_string())?;
+    match envelope.encrypted {
+        Some(encrypted) => {
+            check_version(envelope.version)?;
+            Ok(StoredProjects::Locked(LockedProjects {
+                version: envelope.version,
+                encrypted,
+            }))
+        }
+        None => migrate(envelope.version, envelope.data).map(StoredProjects::Plain),
+    }
 }
 
-pub fn load() -> Option<State> {
-    LocalStorage::get(STORAGE_KEY).ok()
+/// Parses the version 0 data
+fn parse_state(raw: &str) -> Result<Projects, String> {
+    let data = serde_json::from_str(raw).map_err(|e| e.to_string())?;
+    migrate(0, data)
+}
+
+fn migrate(version: u32, mut data: Value) -> Result<Projects, String> {
+    check_version(version)?;
+    for migration in &MIGRATIONS[version as usize..] {
+        data = migration(data)?;
+    }
+
+    let projects: Projects = serde_json::from_value(data).map_err(|e| e.to_string())?;
+    if projects.current >= projects.list.len() {
+        return Err(format!(
+            "the current project is {} but there are {} projects",
+            projects.current,
+            projects.list.len()
+        ));
+    }
+
+    Ok(projects)
+}
+
+fn check_version(version: u32) -> Result<(), String> {
+    if version > SCHEMA_VERSION {
+        return Err(format!(
+            "unsupported version {}, this version of Elephant reads up to version {}",
+            version, SCHEMA_VERSION
+        ));
+    }
+
+    Ok(())
+}
+
+/// Turns the state into the first project. Its blocks are in the Blockly backup, which the key
+/// manager restores when it finds a project without a workspace.
+fn migrate_0_to_1(data: Value) -> Result<Value, String> {
+    let state: State = serde_json::from_value(data).map_err(|e| e.to_string())?;
+    let project = Project {
+        name: "My wallet".to_string(),
+        description: String::new(),
+        created: 0.0,
+        modified: 0.0,
+        settings: BackendSettings::default(),
+        state,
+        workspace: Value::Null,
+        versions: vec![],
+    };
+
+    serde_json::to_value(Projects::new(project)).map_err(|e| e.to_string())
+}
+
+fn load_raw(key: &str) -> Option<String> {
+    LocalStorage::raw().get_item(key).ok().flatten()
+}
+
+/// Copies data that can't be loaded to a new key, so that it doesn't get overwritten
+fn preserve(key: &str, raw: String, reason: String) -> StorageError {
+    let backup_key = format!(
+        "{}{}_{}",
+        BACKUP_STORAGE_KEY_PREFIX,
+        key,
+        js_sys::Date::now() as u64
+    );
+    if let Err(e) = LocalStorage::raw().set_item(&backup_key, &raw) {
+        log::error!("Can't back up `{}`: {:?}", key, e);
+    }
+
+    StorageError {
+        reason,
+        raw,
+        backup_key,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn project(state: State) -> Project {
+        Project {
+            name: "Test".to_string(),
+            description: String::new(),
+            created: 1.0,
+            modified: 2.0,
+            settings: BackendSettings::default(),
+            state,
+            workspace: json!({ "blocks": {} }),
+            versions: vec![],
+        }
+    }
+
+    #[test]
+    fn plain_envelope() {
+        let projects = Projects::new(project(State::new()));
+        let raw = json!({
+            "version": SCHEMA_VERSION,
+            "data": projects,
+        })
+        .to_string();
+
+        assert_eq!(parse_projects(&raw), Ok(StoredProjects::Plain(projects)));
+    }
+
+    #[test]
+    fn encrypted_envelope() {
+        let projects = Projects::new(project(State::new()));
+        let key = EncryptionKey::new("passphrase");
+        let encrypted = key.encrypt(serde_json::to_string(&projects).unwrap().as_bytes());
+        let raw = json!({
+            "version": SCHEMA_VERSION,
+            "encrypted": encrypted,
+        })
+        .to_string();
+
+        let locked = match parse_projects(&raw) {
+            Ok(StoredProjects::Locked(locked)) => locked,
+            other => panic!("expected locked projects, got {:?}", other),
+        };
+        assert_eq!(locked.version, SCHEMA_VERSION);
+        assert_eq!(unlock_projects_with_key(&locked, &key), Ok(projects));
+    }
+
+    #[test]
+    fn invalid_envelopes() {
+        let projects = Projects::new(project(State::new()));
+        // Not wrapped in an envelope
+        assert!(parse_projects(&json!(projects).to_string()).is_err());
+        // Written by a newer version
+        let raw = json!({ "version": SCHEMA_VERSION + 1, "data": projects }).to_string();
+        assert!(parse_projects(&raw)
+            .unwrap_err()
+            .contains("unsupported version"));
+        // Data that doesn't match the version
+        let raw = json!({ "version": SCHEMA_VERSION, "data": { "list": 1 } }).to_string();
+        assert!(parse_projects(&raw).is_err());
+        // No project, or a current project that doesn't exist
+        let raw = json!({ "version": SCHEMA_VERSION, "data": { "current": 0, "list": [] } });
+        assert!(parse_projects(&raw.to_string()).is_err());
+        let mut data = json!(projects);
+        data["current"] = json!(1);
+        let raw = json!({ "version": SCHEMA_VERSION, "data": data }).to_string();
+        assert!(parse_projects(&raw)
+            .unwrap_err()
+            .contains("the current project is 1"));
+        assert!(parse_projects("not json").is_err());
+    }
+
+    #[test]
+    fn migrate_state() {
+        let state = State::new();
+        let projects = parse_state(&serde_json::to_string(&state).unwrap()).unwrap();
+
+        assert_eq!(projects.current, 0);
+        assert_eq!(projects.list.len(), 1);
+        assert_eq!(projects.list[0].name, "My wallet");
+        assert_eq!(projects.list[0].state, state);
+        assert!(projects.list[0].workspace.is_null());
+    }
+
+    #[test]
+    fn invalid_state() {
+        assert!(parse_state(r#"{ "keys": 42 }"#).is_err());
+        assert!(parse_state("").is_err());
+    }
 }
@@ -0,0 +1,166 @@
+use bdk::miniscript::policy::Concrete;
+
+use super::State;
+use crate::hashlock::HashKind;
+use crate::timelock;
+
+/// User-provided values used to instantiate a template
+#[derive(Debug, Clone, PartialEq)]
+pub struct TemplateParams {
+    /// Relative timelock in blocks
+    pub delay: u32,
+    /// Secret of the hash-lock, hashed with SHA256
+    pub secret: String,
+}
+
+impl Default for TemplateParams {
+    fn default() -> Self {
+        TemplateParams {
+            delay: 144,
+            secret: String::new(),
+        }
+    }
+}
+
+/// A ready-made wallet design
+pub struct Template {
+    pub name: &'static str,
+    pub description: &'static str,
+    /// Number of remote keys used, on top of the local key
+    pub remote_keys: usize,
+    pub uses_delay: bool,
+    pub uses_secret: bool,
+    build: fn(&str, &[String], &TemplateParams) -> Concrete<String>,
+}
+
+pub const TEMPLATES: &[Template] = &[
+    Template {
+        name: "Single signature",
+        description: "Only you can spend. The simplest wallet, like most mobile wallets.",
+        remote_keys: 0,
+        uses_delay: false,
+        uses_secret: false,
+        build: |me, _, _| pk(me),
+    },
+    Template {
+        name: "2-of-2 multisig",
+        description: "You and another participant must both sign. Useful for joint accounts where nobody can spend alone, but funds are lost if one key is lost.",
+        remote_keys: 1,
+        uses_delay: false,
+        uses_secret: false,
+        build: |me, others, _| Concrete::And(vec![pk(me), pk(&others[0])]),
+    },
+    Template {
+        name: "2-of-3 multisig",
+        description: "Any two of three participants can spend. A classic for companies or for personal security, since losing one key doesn't lose the funds.",
+        remote_keys: 2,
+        uses_delay: false,
+        uses_secret: false,
+        build: |me, others, _| {
+            Concrete::Threshold(2, vec![pk(me), pk(&others[0]), pk(&others[1])])
+        },
+    },
+    Template {
+        name: "Decaying multisig",
+        description: "All three participants must sign, but after the delay any two of them are enough. Protects against a lost key without weakening the wallet in day-to-day use.",
+        remote_keys: 2,
+        uses_delay: true,
+        uses_secret: false,
+        build: |me, others, params| {
+            Concrete::Threshold(
+                3,
+                vec![
+                    pk(me),
+                    pk(&others[0]),
+                    pk(&others[1]),
+                    Concrete::older(params.delay),
+                ],
+            )
+        },
+    },
+    Template {
+        name: "Inheritance",
+        description: "You spend as usual. If the coins don't move for the whole delay, your heir can spend them with the recovery key.",
+        remote_keys: 1,
+        uses_delay: true,
+        uses_secret: false,
+        build: |me, others, params| {
+            Concrete::Or(vec![
+                (99, pk(me)),
+                (1, Concrete::And(vec![pk(&others[0]), Concrete::older(params.delay)])),
+            ])
+        },
+    },
+    Template {
+        name: "Vault with emergency key",
+        description: "You can only spend coins once they have waited for the delay, while the emergency key can move them immediately. If your key is stolen, the emergency key can sweep the funds before the thief.",
+        remote_keys: 1,
+        uses_delay: true,
+        uses_secret: false,
+        build: |me, others, params| {
+            Concrete::Or(vec![
+                (99, Concrete::And(vec![pk(me), Concrete::older(params.delay)])),
+                (1, pk(&others[0])),
+            ])
+        },
+    },
+    Template {
+        name: "Hash time-locked contract (HTLC)",
+        description: "The other participant can spend by revealing the secret, otherwise you get the coins back after the delay. This is how Lightning payments and atomic swaps work.",
+        remote_keys: 1,
+        uses_delay: true,
+        uses_secret: true,
+        build: |me, others, params| {
+            Concrete::Or(vec![
+                (
+                    9,
+                    Concrete::And(vec![
+                        pk(&others[0]),
+                        Concrete::Sha256(HashKind::Sha256.digest_of_secret(&params.secret)),
+                    ]),
+                ),
+                (1, Concrete::And(vec![pk(me), Concrete::older(params.delay)])),
+            ])
+        },
+    },
+];
+
+impl Template {
+    /// Builds the policy using the local alias and the first remote aliases in `state`
+    pub fn instantiate(
+        &self,
+        state: &State,
+        params: &TemplateParams,
+    ) -> Result<Concrete<String>, String> {
+        let me = state
+            .local_key
+            .as_ref()
+            .map(|(_, alias)| alias.clone())
+            .ok_or_else(|| "Set your local key before using a template".to_string())?;
+        let others = state
+            .participants()
+            .into_iter()
+            .filter(|alias| *alias != me)
+            .take(self.remote_keys)
+            .collect::<Vec<_>>();
+        if others.len() < self.remote_keys {
+            return Err(format!(
+                "This template needs {} remote key(s), add them first",
+                self.remote_keys
+            ));
+        }
+
+        if self.uses_delay {
+            timelock::older_blocks(params.delay)?;
+        }
+        if self.uses_secret && params.secret.is_empty() {
+            return Err("Choose a secret for the hash-lock".to_string());
+        }
+
+        Ok((self.build)(&me, &others, params))
+    }
+}
+
+fn pk(alias: &str) -> Concrete<String> {
+    Concrete::Key(alias.to_string())
+}
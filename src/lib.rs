@@ -0,0 +1,23 @@
+pub mod app;
+mod app_wallet;
+pub mod compiler;
+mod evt;
+mod hashlock;
+mod home;
+mod keymanager;
+mod merge;
+mod policy_node;
+mod policy_view;
+mod portfolio;
+mod psbt_file;
+mod psbt_workspace;
+mod qr;
+mod route;
+mod sign;
+mod tab_create_tx;
+mod timelock;
+mod tracker;
+mod wallet_export;
+mod wallets;
+
+use app_wallet::AppWallet;
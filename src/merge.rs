@@ -1,15 +1,19 @@
+use crate::evt::{AppEvent, EventBus};
+use crate::psbt_file::{parse_psbt_bytes, parse_psbt_str, PsbtDownload, PsbtImport};
+use crate::qr::{psbt_frames, QrImport, QrView};
 use crate::AppWallet;
 use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
 use bdk::*;
-use std::str::FromStr;
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
+use yew_agent::{Dispatched, Dispatcher};
 
 pub enum Msg {
     PsbtInputChanged(InputEvent),
     DeletePsbt(usize),
     //NewPsbtField,
     AddPsbt,
+    PsbtImported(Result<PartiallySignedTransaction, String>),
     Merge,
     BroadcastTriggered,
     BroadcastFinished(Result<(), String>),
@@ -18,17 +22,20 @@ pub enum Msg {
 #[derive(Properties, PartialEq)]
 pub struct Props {
     pub wallet: AppWallet,
+    /// PSBTs to merge, kept in the wallet's PSBT workspace
+    pub psbts: Vec<PartiallySignedTransaction>,
+    pub on_add: Callback<PartiallySignedTransaction>,
+    pub on_remove: Callback<usize>,
 }
 
 pub struct Merge {
     psbt_input: Option<Result<PartiallySignedTransaction, String>>,
     psbt_input_text: String,
-    psbts: Vec<(PartiallySignedTransaction, usize)>,
     merged_psbt: Option<Result<PartiallySignedTransaction, String>>,
     wallet: AppWallet,
     is_broadcasting: bool,
     broadcast_result: Option<Result<(), String>>,
-    key_n: usize,
+    dispatcher: Dispatcher<EventBus>,
 }
 
 impl Component for Merge {
@@ -40,20 +47,24 @@ impl Component for Merge {
         Self {
             psbt_input: None,
             psbt_input_text: "".to_string(),
-            psbts: vec![],
             merged_psbt: None,
             wallet: props.wallet.clone(),
-            key_n: 0,
             is_broadcasting: false,
             broadcast_result: None,
+            dispatcher: EventBus::dispatcher(),
         }
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let merge = ctx.link().callback(|_| Msg::Merge);
         let add_psbt = ctx.link().callback(|_| Msg::AddPsbt);
+        let on_import = ctx.link().callback(Msg::PsbtImported);
+        let on_qr_import = ctx.link().callback(|res: Result<Vec<u8>, String>| {
+            Msg::PsbtImported(res.and_then(|b| parse_psbt_bytes(&b)))
+        });
         let broadcast = ctx.link().callback(|_| Msg::BroadcastTriggered);
-        let merge_disabled = self.psbts.is_empty();
+        let psbts = &ctx.props().psbts;
+        let merge_disabled = psbts.is_empty();
         let (merged_psbt, merge_error) = match &self.merged_psbt {
             Some(Ok(p)) => (p.clone().to_string(), "".to_string()),
             Some(Err(e)) => ("".to_string(), e.to_string()),
@@ -70,7 +81,7 @@ impl Component for Merge {
 
         let broadcast_result_msg = match &self.broadcast_result {
             Some(Ok(())) => format!(
-                "Successfully broadcasted transaction with txid: {:?}",
+                "Successfully broadcasted transaction with txid: {:?}. You'll be notified when it confirms.",
                 self.merged_psbt
                     .as_ref()
                     .unwrap()
@@ -92,12 +103,14 @@ impl Component for Merge {
                     <input type="text" style="margin-right: 5px" class={classes!("form-control", psbt_input_is_invalid)} id="addPsbt" {oninput} value={self.psbt_input_text.clone()} placeholder="Paste your PSBT here..."/>
                     <button class="btn btn-primary" onclick={add_psbt} disabled={ add_psbt_disabled }> <i class="bi bi-plus-lg"></i> </button>
                 </div>
+                <PsbtImport {on_import}/>
+                <QrImport on_import={on_qr_import}/>
                 {
-                    for self.psbts.iter().enumerate().map(|(i, (psbt, key))| {
+                    for psbts.iter().enumerate().map(|(i, psbt)| {
                         let delete_psbt = ctx.link().callback(move |_| Msg::DeletePsbt(i));
                         html! {
                             <div class="same-line">
-                                <input type="text" style="margin-right: 5px" class="form-control" disabled=true key={*key} value={psbt.to_string()}/>
+                                <input type="text" style="margin-right: 5px" class="form-control" disabled=true key={i} value={psbt.to_string()}/>
                                 <button class="btn btn-danger" onclick={delete_psbt} ><i class="bi bi-trash"></i></button>
                             </div>
                         }
@@ -108,6 +121,10 @@ impl Component for Merge {
                 <div class="invalid-feedback">
                 { format!("Error merging PSBTs: {}", merge_error) }
                 </div>
+                if let Some(Ok(psbt)) = &self.merged_psbt {
+                    <PsbtDownload psbt={psbt.clone()} name="merged"/>
+                    <QrView frames={psbt_frames(psbt)}/>
+                }
                 <button class="btn btn-primary daniela-button" onclick={broadcast} disabled={ broadcast_disabled }>{ if self.is_broadcasting { "Broadcasting..." } else { "Broadcast" } }</button>
                 <div>
                     <label> { broadcast_result_msg } </label>
@@ -120,18 +137,20 @@ impl Component for Merge {
         match msg {
             Msg::DeletePsbt(i) => {
                 log::info!("Delete");
-                self.psbts.remove(i);
+                ctx.props().on_remove.emit(i);
                 true
             }
             Msg::Merge => {
                 log::info!("Merge");
-                let mut temp = self.psbts[0].clone().0;
-                for psbt in &self.psbts[1..] {
-                    if let Err(e) = temp.combine(psbt.clone().0) {
+                let psbts = &ctx.props().psbts;
+                let mut temp = psbts[0].clone();
+                for psbt in &psbts[1..] {
+                    if let Err(e) = temp.combine(psbt.clone()) {
                         self.merged_psbt = Some(Err(e.to_string()));
                         return true;
                     }
                 }
+                self.dispatcher.send(AppEvent::PsbtMerged(temp.clone()));
                 self.merged_psbt = Some(Ok(temp));
                 true
             }
@@ -142,14 +161,13 @@ impl Component for Merge {
                 let finalized = self
                     .wallet
                     .borrow()
-                    .0
                     .finalize_psbt(&mut merged_psbt, SignOptions::default());
                 match finalized {
                     Ok(true) => {
                         let tx = merged_psbt.extract_tx();
-                        let wallet_cloned = self.wallet.0.clone();
+                        let blockchain = self.wallet.blockchain();
                         ctx.link().send_future(async move {
-                            let res = wallet_cloned.borrow().1.broadcast(&tx).await;
+                            let res = blockchain.broadcast(&tx).await;
                             Msg::BroadcastFinished(res.map_err(|e| e.to_string()))
                         });
                     }
@@ -165,14 +183,27 @@ impl Component for Merge {
             }
             Msg::BroadcastFinished(res) => {
                 self.is_broadcasting = false;
+                if let Some(Ok(psbt)) = &self.merged_psbt {
+                    let txid = psbt.unsigned_tx.txid();
+                    self.dispatcher
+                        .send(AppEvent::Broadcast(res.clone().map(|_| txid)));
+                }
                 self.broadcast_result = Some(res);
                 true
             }
             Msg::AddPsbt => {
                 let psbt = self.psbt_input.clone().unwrap().unwrap();
-                self.key_n += 1;
-                self.psbts.push((psbt, self.key_n));
+                ctx.props().on_add.emit(psbt);
                 self.psbt_input_text = "".to_string();
+                self.psbt_input = None;
+                true
+            }
+            Msg::PsbtImported(Ok(psbt)) => {
+                ctx.props().on_add.emit(psbt);
+                true
+            }
+            Msg::PsbtImported(Err(e)) => {
+                self.psbt_input = Some(Err(e));
                 true
             }
             Msg::PsbtInputChanged(e) => {
@@ -186,11 +217,9 @@ impl Component for Merge {
                 if &psbt == "" {
                     self.psbt_input = None;
                 } else {
-                    self.psbt_input = Some(
-                        PartiallySignedTransaction::from_str(&psbt).map_err(|e| e.to_string()),
-                    );
+                    self.psbt_input = Some(parse_psbt_str(&psbt));
                 }
-                log::info!("Psbt parsing: {:?}", self.psbts);
+                log::info!("Psbt parsing: {:?}", self.psbt_input);
                 true
             }
         }
@@ -8,6 +8,7 @@ use web_sys::HtmlInputElement;
 use yew::prelude::*;
 
 use crate::policy_view::Selection;
+use crate::timelock;
 
 const INDENT_PX_UNIT: u32 = 20;
 
@@ -45,12 +46,14 @@ impl PolicyNodeProps {
             SatisfiableItem::Hash160Preimage { hash } => {
                 format!("Double-RIPEMD160 Preimage of {}", hash.to_string())
             }
-            SatisfiableItem::AbsoluteTimelock { value } => {
-                format!("Absolute Timelock of {}", value.to_string())
-            }
-            SatisfiableItem::RelativeTimelock { value } => {
-                format!("Relative Timelock of {}", value.to_string())
-            }
+            SatisfiableItem::AbsoluteTimelock { value } => format!(
+                "Absolute Timelock until {}",
+                timelock::describe_after(value.to_consensus_u32())
+            ),
+            SatisfiableItem::RelativeTimelock { value } => format!(
+                "Relative Timelock of {}",
+                timelock::describe_older(value.to_consensus_u32())
+            ),
             SatisfiableItem::Multisig { keys, threshold } => {
                 format!("{} of {} MultiSig:", threshold, keys.len())
             }
@@ -0,0 +1,181 @@
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+
+use crate::home::satcommify;
+use crate::wallets::{OpenWallet, WalletTx};
+
+/// Number of transactions shown in the recent activity
+const RECENT_ACTIVITY: usize = 10;
+
+pub enum PortfolioMsg {
+    NameChanged(InputEvent),
+    Keep,
+}
+
+#[derive(PartialEq, Properties)]
+pub struct PortfolioProps {
+    pub wallets: Vec<OpenWallet>,
+    pub current: Option<usize>,
+    /// Id of the wallet built from the wallet creator, if any
+    pub draft: Option<usize>,
+    pub on_open: Callback<usize>,
+    pub on_sync: Callback<usize>,
+    pub on_close: Callback<usize>,
+    pub on_keep: Callback<String>,
+}
+
+/// Balances of every open wallet, and their latest transactions
+pub struct Portfolio {
+    name: String,
+}
+
+impl Portfolio {
+    fn wallets(&self, ctx: &Context<Self>) -> Html {
+        let props = ctx.props();
+
+        html! {
+            <table class="table table-sm daniela-table">
+                <thead>
+                    <tr>
+                        <th>{ "Wallet" }</th>
+                        <th class="daniela-table-align-right">{ "Balance" }</th>
+                        <th>{ "Status" }</th>
+                        <th></th>
+                    </tr>
+                </thead>
+                <tbody>
+                { for props.wallets.iter().map(|w| {
+                    let id = w.id;
+                    let is_draft = props.draft == Some(id);
+                    let status = match (w.is_loading, &w.sync_error, w.is_synced) {
+                        (true, _, _) => "Syncing...",
+                        (false, Some(_), _) => "Sync failed",
+                        (false, None, true) => "Synced",
+                        (false, None, false) => "Not synced",
+                    };
+                    html! {
+                        <tr class={classes!((props.current == Some(id)).then_some("table-active"))}>
+                            <td>
+                                { &w.name }
+                                if is_draft {
+                                    <span class="badge bg-info ms-2">{ "draft" }</span>
+                                }
+                                if w.wallet.is_watch_only() {
+                                    <span class="badge bg-secondary ms-2">{ "watch-only" }</span>
+                                }
+                            </td>
+                            <td class="daniela-table-align-right">
+                                { format!("{} sats", satcommify(w.balance.get_spendable())) }
+                                if w.balance.untrusted_pending > 0 {
+                                    <div class="balance-unconfirmed">{ format!("+ {} sats unconfirmed", satcommify(w.balance.untrusted_pending)) }</div>
+                                }
+                            </td>
+                            <td title={w.sync_error.clone()}>{ status }</td>
+                            <td class="text-end">
+                                <button type="button" class="btn btn-sm btn-outline-primary me-1" onclick={props.on_open.reform(move |_| id)}>{ "Open" }</button>
+                                <button type="button" class="btn btn-sm btn-outline-primary me-1" onclick={props.on_sync.reform(move |_| id)} disabled={w.is_loading}>{ "Sync" }</button>
+                                if !is_draft {
+                                    <button type="button" class="btn btn-sm btn-outline-danger" onclick={props.on_close.reform(move |_| id)}><i class="bi bi-x-lg"></i></button>
+                                }
+                            </td>
+                        </tr>
+                    }
+                })}
+                </tbody>
+            </table>
+        }
+    }
+
+    fn keep_draft(&self, ctx: &Context<Self>) -> Html {
+        if ctx.props().draft.is_none() {
+            return html! {};
+        }
+
+        let oninput = ctx.link().callback(PortfolioMsg::NameChanged);
+        let onclick = ctx.link().callback(|_| PortfolioMsg::Keep);
+
+        html! {
+            <div class="input-group mb-4">
+                <input type="text" class="form-control" {oninput} value={self.name.clone()} placeholder="Name of the wallet" />
+                <button type="button" class="btn btn-primary" {onclick} disabled={self.name.trim().is_empty()}>{ "Keep the wallet creator's wallet" }</button>
+            </div>
+        }
+    }
+
+    fn recent_activity(&self, ctx: &Context<Self>) -> Html {
+        let mut activity = ctx
+            .props()
+            .wallets
+            .iter()
+            .flat_map(|w| w.transactions.iter().map(move |tx| (&w.name, tx)))
+            .collect::<Vec<(&String, &WalletTx)>>();
+        // Unconfirmed first, then the most recent
+        activity.sort_by_key(|(_, tx)| std::cmp::Reverse(tx.height.unwrap_or(u32::MAX)));
+        activity.truncate(RECENT_ACTIVITY);
+
+        html! {
+            <table class="table-sm daniela-table">
+                <tbody>
+                { for activity.into_iter().map(|(name, tx)| html! {
+                    <tr>
+                        <td>{ name }</td>
+                        <td scope="row">{ &tx.txid }</td>
+                        <td>{ tx.height.map(|h| format!("block {}", h)).unwrap_or_else(|| "unconfirmed".to_string()) }</td>
+                        <td class="daniela-table-align-right">{ format!("{} {} sats", if tx.amount >= 0 { "+" } else { "-" }, satcommify(tx.amount.unsigned_abs())) }</td>
+                    </tr>
+                })}
+                </tbody>
+            </table>
+        }
+    }
+}
+
+impl Component for Portfolio {
+    type Message = PortfolioMsg;
+    type Properties = PortfolioProps;
+
+    fn create(_ctx: &Context<Self>) -> Self {
+        Portfolio {
+            name: String::new(),
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            PortfolioMsg::NameChanged(e) => {
+                self.name = e.target_unchecked_into::<HtmlInputElement>().value();
+                true
+            }
+            PortfolioMsg::Keep => {
+                ctx.props().on_keep.emit(self.name.trim().to_string());
+                self.name = String::new();
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let (spendable, pending) = ctx.props().wallets.iter().fold((0, 0), |(s, p), w| {
+            (
+                s + w.balance.get_spendable(),
+                p + w.balance.untrusted_pending,
+            )
+        });
+
+        html! {
+            <div class="daniela">
+                <div class="daniela-home text-center">
+                    <div class="balance-wrapper">
+                        <div class="balance">{ format!("{} sats", satcommify(spendable)) }</div>
+                        <div class="balance-unconfirmed">{ format!("+ {} sats unconfirmed", satcommify(pending)) }</div>
+                    </div>
+                    <div class="form-text">{ format!("Total of {} wallet(s)", ctx.props().wallets.len()) }</div>
+                </div>
+                { self.wallets(ctx) }
+                { self.keep_draft(ctx) }
+                <h4>{ "Recent activity" }</h4>
+                { self.recent_activity(ctx) }
+            </div>
+        }
+    }
+}
@@ -0,0 +1,218 @@
+use std::str::FromStr;
+
+use bdk::bitcoin::consensus::encode::{deserialize, serialize};
+use bdk::bitcoin::hashes::hex::{FromHex, ToHex};
+use bdk::bitcoin::psbt::Psbt;
+use gloo_file::{Blob, File, ObjectUrl};
+use web_sys::{DragEvent, HtmlInputElement};
+use yew::prelude::*;
+
+/// Magic bytes at the beginning of every binary (BIP174) PSBT
+const PSBT_MAGIC: &[u8] = b"psbt\xff";
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PsbtFormat {
+    Binary,
+    Base64,
+    Hex,
+}
+
+impl PsbtFormat {
+    fn all() -> [PsbtFormat; 3] {
+        [PsbtFormat::Binary, PsbtFormat::Base64, PsbtFormat::Hex]
+    }
+
+    fn label(&self) -> &'static str {
+        match self {
+            PsbtFormat::Binary => ".psbt",
+            PsbtFormat::Base64 => "base64",
+            PsbtFormat::Hex => "hex",
+        }
+    }
+
+    fn extension(&self) -> &'static str {
+        match self {
+            PsbtFormat::Binary => "psbt",
+            PsbtFormat::Base64 => "b64.txt",
+            PsbtFormat::Hex => "hex.txt",
+        }
+    }
+}
+
+/// Parses a PSBT pasted as text, either in base64 or hex
+pub fn parse_psbt_str(s: &str) -> Result<Psbt, String> {
+    let s = s.trim();
+    let base64_err = match Psbt::from_str(s) {
+        Ok(psbt) => return Ok(psbt),
+        Err(e) => e,
+    };
+
+    match Vec::<u8>::from_hex(s) {
+        Ok(bytes) => deserialize(&bytes).map_err(|e| e.to_string()),
+        Err(_) => Err(base64_err.to_string()),
+    }
+}
+
+/// Parses the content of a PSBT file, which can be binary, base64 or hex
+pub fn parse_psbt_bytes(bytes: &[u8]) -> Result<Psbt, String> {
+    if bytes.starts_with(PSBT_MAGIC) {
+        return deserialize(bytes).map_err(|e| e.to_string());
+    }
+
+    let s = std::str::from_utf8(bytes).map_err(|_| "not a valid PSBT file")?;
+    parse_psbt_str(s)
+}
+
+pub fn encode_psbt(psbt: &Psbt, format: PsbtFormat) -> Vec<u8> {
+    match format {
+        PsbtFormat::Binary => serialize(psbt),
+        PsbtFormat::Base64 => psbt.to_string().into_bytes(),
+        PsbtFormat::Hex => serialize(psbt).to_hex().into_bytes(),
+    }
+}
+
+pub enum PsbtImportMsg {
+    FilesSelected(Vec<File>),
+    FileLoaded(Result<Psbt, String>),
+    DragOver(bool),
+}
+
+#[derive(PartialEq, Properties)]
+pub struct PsbtImportProps {
+    pub on_import: Callback<Result<Psbt, String>>,
+}
+
+/// Drop zone and file picker accepting binary, base64 and hex PSBT files
+pub struct PsbtImport {
+    is_dragging: bool,
+}
+
+impl Component for PsbtImport {
+    type Message = PsbtImportMsg;
+    type Properties = PsbtImportProps;
+
+    fn create(_ctx: &Context<Self>) -> Self {
+        Self { is_dragging: false }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            PsbtImportMsg::FilesSelected(files) => {
+                self.is_dragging = false;
+                for file in files {
+                    ctx.link().send_future(async move {
+                        let res = gloo_file::futures::read_as_bytes(&file)
+                            .await
+                            .map_err(|e| e.to_string())
+                            .and_then(|bytes| parse_psbt_bytes(&bytes))
+                            .map_err(|e| format!("{}: {}", file.name(), e));
+                        PsbtImportMsg::FileLoaded(res)
+                    });
+                }
+                true
+            }
+            PsbtImportMsg::FileLoaded(res) => {
+                ctx.props().on_import.emit(res);
+                false
+            }
+            PsbtImportMsg::DragOver(is_dragging) => {
+                let changed = self.is_dragging != is_dragging;
+                self.is_dragging = is_dragging;
+                changed
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let onchange = ctx.link().callback(|e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            let files = files_from_list(input.files());
+            input.set_value("");
+            PsbtImportMsg::FilesSelected(files)
+        });
+        let ondrop = ctx.link().callback(|e: DragEvent| {
+            e.prevent_default();
+            let files = files_from_list(e.data_transfer().and_then(|dt| dt.files()));
+            PsbtImportMsg::FilesSelected(files)
+        });
+        let ondragover = ctx.link().callback(|e: DragEvent| {
+            e.prevent_default();
+            PsbtImportMsg::DragOver(true)
+        });
+        let ondragleave = ctx.link().callback(|_| PsbtImportMsg::DragOver(false));
+        let dragging = self.is_dragging.then_some("psbt-drop-zone-active");
+
+        html! {
+            <label class={classes!("psbt-drop-zone", dragging)} {ondrop} {ondragover} {ondragleave}>
+                <i class="bi bi-file-earmark-arrow-up"></i>
+                { " Drop a PSBT file here or click to choose one" }
+                <input type="file" class="d-none" multiple=true accept=".psbt,.txt,.b64,.hex" {onchange}/>
+            </label>
+        }
+    }
+}
+
+pub fn files_from_list(list: Option<web_sys::FileList>) -> Vec<File> {
+    list.map(|list| {
+        (0..list.length())
+            .filter_map(|i| list.get(i))
+            .map(File::from)
+            .collect()
+    })
+    .unwrap_or_default()
+}
+
+#[derive(PartialEq, Properties)]
+pub struct PsbtDownloadProps {
+    pub psbt: Psbt,
+    /// File name without extension
+    pub name: String,
+}
+
+/// Download links for a PSBT in every supported format
+pub struct PsbtDownload {
+    urls: Vec<(PsbtFormat, ObjectUrl)>,
+}
+
+impl PsbtDownload {
+    fn object_urls(psbt: &Psbt) -> Vec<(PsbtFormat, ObjectUrl)> {
+        PsbtFormat::all()
+            .into_iter()
+            .map(|format| {
+                let data = encode_psbt(psbt, format);
+                (format, ObjectUrl::from(Blob::new(data.as_slice())))
+            })
+            .collect()
+    }
+}
+
+impl Component for PsbtDownload {
+    type Message = ();
+    type Properties = PsbtDownloadProps;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        Self {
+            urls: Self::object_urls(&ctx.props().psbt),
+        }
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        self.urls = Self::object_urls(&ctx.props().psbt);
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let name = &ctx.props().name;
+
+        html! {
+            <div class="btn-group btn-group-sm mb-2" role="group">
+                <span class="btn btn-outline-secondary disabled"><i class="bi bi-download"></i></span>
+                { for self.urls.iter().map(|(format, url)| html! {
+                    <a class="btn btn-outline-secondary" href={url.to_string()} download={format!("{}.{}", name, format.extension())}>
+                        { format.label() }
+                    </a>
+                })}
+            </div>
+        }
+    }
+}
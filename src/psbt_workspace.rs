@@ -0,0 +1,264 @@
+use std::str::FromStr;
+
+use bdk::bitcoin::hashes::hex::ToHex;
+use bdk::bitcoin::hashes::{sha256, Hash};
+use bdk::bitcoin::psbt::Psbt;
+use bdk::bitcoin::Txid;
+use bdk::wallet::signer::SignOptions;
+use bdk::KeychainKind;
+use gloo_storage::{LocalStorage, Storage};
+use serde::{Deserialize, Serialize};
+use yew::prelude::*;
+
+use crate::home::satcommify;
+use crate::AppWallet;
+
+/// Prefix of the storage keys, followed by the hash of the wallet's public descriptor
+const STORAGE_PREFIX: &str = "PSBTS_";
+
+/// PSBTs in base64, as saved in the browser's storage
+#[derive(Default, Serialize, Deserialize)]
+struct StoredWorkspace {
+    psbts: Vec<String>,
+    to_merge: Vec<String>,
+}
+
+/// Copy of `psbt` without the hash-lock preimages, which must not be saved in clear text
+fn without_preimages(psbt: &Psbt) -> Psbt {
+    let mut psbt = psbt.clone();
+    for input in &mut psbt.inputs {
+        input.sha256_preimages.clear();
+        input.hash256_preimages.clear();
+        input.ripemd160_preimages.clear();
+        input.hash160_preimages.clear();
+    }
+
+    psbt
+}
+
+/// Transactions in progress of a wallet, kept across tab switches and reloads
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct PsbtWorkspace {
+    storage_key: Option<String>,
+    /// One PSBT per transaction, with every signature collected so far
+    pub psbts: Vec<Psbt>,
+    /// PSBTs waiting in the merge tab
+    pub to_merge: Vec<Psbt>,
+    /// Transaction opened in the sign tab
+    to_sign: Option<Txid>,
+}
+
+impl PsbtWorkspace {
+    pub fn load(wallet: &AppWallet) -> Self {
+        // Watch-only and full copies of a wallet share their PSBTs, and no private key ends up in
+        // the storage key
+        let descriptor = wallet
+            .borrow()
+            .public_descriptor(KeychainKind::External)
+            .ok()
+            .flatten()
+            .map(|d| d.to_string())
+            .unwrap_or_default();
+        let storage_key = format!(
+            "{}{}",
+            STORAGE_PREFIX,
+            sha256::Hash::hash(descriptor.as_bytes()).to_hex()
+        );
+
+        let stored = LocalStorage::get::<StoredWorkspace>(&storage_key).unwrap_or_default();
+        let parse = |psbts: Vec<String>| {
+            psbts
+                .iter()
+                .filter_map(|p| Psbt::from_str(p).ok())
+                .collect()
+        };
+
+        PsbtWorkspace {
+            storage_key: Some(storage_key),
+            psbts: parse(stored.psbts),
+            to_merge: parse(stored.to_merge),
+            to_sign: None,
+        }
+    }
+
+    fn save(&self) {
+        let key = match &self.storage_key {
+            Some(key) => key,
+            None => return,
+        };
+        let encode = |psbts: &[Psbt]| {
+            psbts
+                .iter()
+                .map(|p| without_preimages(p).to_string())
+                .collect()
+        };
+        let stored = StoredWorkspace {
+            psbts: encode(&self.psbts),
+            to_merge: encode(&self.to_merge),
+        };
+        if let Err(e) = LocalStorage::set(key, stored) {
+            log::warn!("Failed to save the PSBTs: {}", e);
+        }
+    }
+
+    fn get(&self, txid: Txid) -> Option<&Psbt> {
+        self.psbts.iter().find(|p| p.unsigned_tx.txid() == txid)
+    }
+
+    /// Adds a PSBT, combining it with the one of the same transaction if there's any
+    pub fn add(&mut self, psbt: Psbt) {
+        let txid = psbt.unsigned_tx.txid();
+        match self.psbts.iter_mut().find(|p| p.unsigned_tx.txid() == txid) {
+            Some(existing) => {
+                if let Err(e) = existing.combine(psbt) {
+                    log::warn!("Failed to combine the PSBTs of {}: {}", txid, e);
+                }
+            }
+            None => self.psbts.push(psbt),
+        }
+        self.save();
+    }
+
+    /// Forgets a transaction, once broadcast or abandoned
+    pub fn remove(&mut self, txid: Txid) {
+        self.psbts.retain(|p| p.unsigned_tx.txid() != txid);
+        self.to_merge.retain(|p| p.unsigned_tx.txid() != txid);
+        if self.to_sign == Some(txid) {
+            self.to_sign = None;
+        }
+        self.save();
+    }
+
+    pub fn queue_merge(&mut self, psbt: Psbt) {
+        if !self.to_merge.contains(&psbt) {
+            self.to_merge.push(psbt);
+            self.save();
+        }
+    }
+
+    /// Queues the transaction's PSBT in the merge tab
+    pub fn queue_merge_tx(&mut self, txid: Txid) {
+        if let Some(psbt) = self.get(txid).cloned() {
+            self.queue_merge(psbt);
+        }
+    }
+
+    pub fn unqueue_merge(&mut self, index: usize) {
+        if index < self.to_merge.len() {
+            self.to_merge.remove(index);
+            self.save();
+        }
+    }
+
+    pub fn contains(&self, txid: Txid) -> bool {
+        self.get(txid).is_some()
+    }
+
+    pub fn select_to_sign(&mut self, txid: Txid) {
+        self.to_sign = Some(txid);
+    }
+
+    /// PSBT opened in the sign tab: the one picked by the user, or the latest one
+    pub fn to_sign(&self) -> Option<&Psbt> {
+        self.to_sign
+            .and_then(|txid| self.get(txid))
+            .or_else(|| self.psbts.last())
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PsbtStatus {
+    Unsigned,
+    /// Signed, but not enough to spend yet, with the number of signatures
+    Signed(usize),
+    /// Ready to be broadcast
+    Complete,
+}
+
+impl PsbtStatus {
+    pub fn of(wallet: &AppWallet, psbt: &Psbt) -> Self {
+        let mut finalized = psbt.clone();
+        let is_complete = wallet
+            .borrow()
+            .finalize_psbt(&mut finalized, SignOptions::default())
+            .unwrap_or(false);
+        if is_complete {
+            return PsbtStatus::Complete;
+        }
+
+        let signatures = psbt
+            .inputs
+            .iter()
+            .map(|i| i.partial_sigs.len() + i.tap_script_sigs.len() + i.tap_key_sig.iter().count())
+            .sum();
+        match signatures {
+            0 => PsbtStatus::Unsigned,
+            n => PsbtStatus::Signed(n),
+        }
+    }
+
+    fn description(&self) -> String {
+        match self {
+            PsbtStatus::Unsigned => "Unsigned".to_string(),
+            PsbtStatus::Signed(n) => format!("{} signature(s), not complete", n),
+            PsbtStatus::Complete => "Ready to broadcast".to_string(),
+        }
+    }
+}
+
+#[derive(PartialEq, Properties)]
+pub struct PsbtWorkspaceProps {
+    pub wallet: AppWallet,
+    pub workspace: PsbtWorkspace,
+    pub on_sign: Callback<Txid>,
+    pub on_merge: Callback<Txid>,
+    pub on_remove: Callback<Txid>,
+}
+
+/// Transactions in progress of the current wallet, with their signing status
+pub struct PsbtWorkspaceView;
+
+impl Component for PsbtWorkspaceView {
+    type Message = ();
+    type Properties = PsbtWorkspaceProps;
+
+    fn create(_ctx: &Context<Self>) -> Self {
+        PsbtWorkspaceView
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let props = ctx.props();
+        if props.workspace.psbts.is_empty() {
+            return html! {};
+        }
+        let can_sign = props.wallet.can_sign();
+
+        html! {
+            <div class="daniela psbt-workspace">
+                <h4>{ "Transactions in progress" }</h4>
+                <table class="table table-sm daniela-table">
+                    <tbody>
+                    { for props.workspace.psbts.iter().map(|psbt| {
+                        let txid = psbt.unsigned_tx.txid();
+                        let status = PsbtStatus::of(&props.wallet, psbt);
+                        let amount = psbt.unsigned_tx.output.iter().map(|o| o.value).sum::<u64>();
+                        let is_queued = props.workspace.to_merge.iter().any(|p| p.unsigned_tx.txid() == txid);
+                        html! {
+                            <tr>
+                                <td scope="row" class="text-truncate" style="max-width: 200px" title={txid.to_string()}>{ txid.to_string() }</td>
+                                <td class="daniela-table-align-right">{ format!("{} sats", satcommify(amount)) }</td>
+                                <td>{ status.description() }</td>
+                                <td class="text-end">
+                                    <button type="button" class="btn btn-sm btn-outline-primary me-1" onclick={props.on_sign.reform(move |_| txid)} disabled={!can_sign || status == PsbtStatus::Complete}>{ "Sign this" }</button>
+                                    <button type="button" class="btn btn-sm btn-outline-primary me-1" onclick={props.on_merge.reform(move |_| txid)} disabled={is_queued}>{ "Add to merge" }</button>
+                                    <button type="button" class="btn btn-sm btn-outline-danger" onclick={props.on_remove.reform(move |_| txid)}><i class="bi bi-trash"></i></button>
+                                </td>
+                            </tr>
+                        }
+                    })}
+                    </tbody>
+                </table>
+            </div>
+        }
+    }
+}
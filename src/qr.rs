@@ -0,0 +1,479 @@
+use std::collections::BTreeMap;
+
+use bdk::bitcoin::consensus::encode::serialize;
+use bdk::bitcoin::psbt::Psbt;
+use gloo_file::File;
+use gloo_timers::callback::Interval;
+use qrcode::{render::svg, EcLevel, QrCode};
+use web_sys::HtmlInputElement;
+use yew::prelude::*;
+
+use crate::psbt_file::files_from_list;
+
+/// Payloads longer than this (in characters) are split into multiple animated frames
+const MAX_FRAME_LEN: usize = 400;
+/// Time each frame of an animated QR code is shown for, in milliseconds
+const FRAME_INTERVAL_MS: u32 = 500;
+
+const BASE32_ALPHABET: &[u8; 32] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+const BASE36_ALPHABET: &[u8; 36] = b"0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZ";
+
+/// File types defined by BBQr that we know how to produce
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum BbqrType {
+    Psbt,
+    UnicodeText,
+}
+
+impl BbqrType {
+    fn code(&self) -> char {
+        match self {
+            BbqrType::Psbt => 'P',
+            BbqrType::UnicodeText => 'U',
+        }
+    }
+}
+
+/// Renders `data` as a QR code SVG
+pub fn qr_svg(data: &str) -> Result<String, String> {
+    let code = QrCode::with_error_correction_level(data, EcLevel::L).map_err(|e| e.to_string())?;
+    Ok(code
+        .render::<svg::Color>()
+        .min_dimensions(250, 250)
+        .quiet_zone(true)
+        .build())
+}
+
+/// Builds a BIP21 URI for an address
+pub fn bip21_uri(address: &str) -> String {
+    format!("bitcoin:{}", address)
+}
+
+/// Extracts the address from a BIP21 URI, or returns the input if it's not a URI
+pub fn address_from_bip21(uri: &str) -> String {
+    let uri = uri.trim();
+    let without_scheme = match uri.get(..8) {
+        Some(scheme) if scheme.eq_ignore_ascii_case("bitcoin:") => &uri[8..],
+        _ => uri,
+    };
+    without_scheme
+        .split('?')
+        .next()
+        .unwrap_or_default()
+        .to_string()
+}
+
+/// Splits `data` into BBQr frames, using the uncompressed base32 encoding
+pub fn bbqr_encode(data: &[u8], ty: BbqrType) -> Vec<String> {
+    let encoded = base32_encode(data);
+    let n_parts = encoded.len().div_ceil(MAX_FRAME_LEN).clamp(1, 36 * 36 - 1);
+    // Every part except the last one must be a multiple of 8 characters, so that
+    // parts can be decoded independently
+    let part_len = encoded.len().div_ceil(n_parts).div_ceil(8) * 8;
+    let chunks = encoded
+        .as_bytes()
+        .chunks(part_len.max(8))
+        .collect::<Vec<_>>();
+
+    chunks
+        .iter()
+        .enumerate()
+        .map(|(i, chunk)| {
+            format!(
+                "B$2{}{}{}{}",
+                ty.code(),
+                base36(chunks.len()),
+                base36(i),
+                std::str::from_utf8(chunk).expect("base32 is ascii")
+            )
+        })
+        .collect()
+}
+
+/// Animated BBQr frames for a binary PSBT
+pub fn psbt_frames(psbt: &Psbt) -> Vec<String> {
+    bbqr_encode(&serialize(psbt), BbqrType::Psbt)
+}
+
+/// Reassembles a BBQr payload from frames scanned in any order
+#[derive(Default)]
+pub struct BbqrJoiner {
+    total: usize,
+    parts: BTreeMap<usize, String>,
+}
+
+impl BbqrJoiner {
+    pub fn is_bbqr(frame: &str) -> bool {
+        frame.starts_with("B$")
+    }
+
+    pub fn add(&mut self, frame: &str) -> Result<(), String> {
+        if !frame.is_ascii() {
+            return Err("BBQr frame contains non-ASCII characters".to_string());
+        }
+        let header = frame.get(..8).ok_or("BBQr frame is too short")?;
+        if &header[2..3] != "2" {
+            return Err(format!("unsupported BBQr encoding `{}`", &header[2..3]));
+        }
+        let total = parse_base36(&header[4..6])?;
+        let index = parse_base36(&header[6..8])?;
+        if index >= total {
+            return Err("BBQr frame index is out of range".to_string());
+        }
+        if self.total != 0 && self.total != total {
+            return Err("BBQr frame belongs to a different payload".to_string());
+        }
+
+        self.total = total;
+        self.parts.insert(index, frame[8..].to_string());
+        Ok(())
+    }
+
+    pub fn progress(&self) -> (usize, usize) {
+        (self.parts.len(), self.total)
+    }
+
+    pub fn is_complete(&self) -> bool {
+        self.total != 0 && self.parts.len() == self.total
+    }
+
+    pub fn finish(&self) -> Result<Vec<u8>, String> {
+        if !self.is_complete() {
+            return Err("missing BBQr frames".to_string());
+        }
+        base32_decode(&self.parts.values().cloned().collect::<String>())
+    }
+}
+
+/// Decodes the first QR code found in an image file (PNG or JPEG)
+pub fn decode_qr_image(bytes: &[u8]) -> Result<String, String> {
+    let image = image::load_from_memory(bytes)
+        .map_err(|e| e.to_string())?
+        .to_luma8();
+    let mut prepared = rqrr::PreparedImage::prepare(image);
+    let grids = prepared.detect_grids();
+    let grid = grids.first().ok_or("no QR code found in the image")?;
+    let (_, content) = grid.decode().map_err(|e| e.to_string())?;
+
+    Ok(content)
+}
+
+fn base36(n: usize) -> String {
+    let (hi, lo) = (n / 36, n % 36);
+    format!(
+        "{}{}",
+        BASE36_ALPHABET[hi] as char, BASE36_ALPHABET[lo] as char
+    )
+}
+
+fn parse_base36(s: &str) -> Result<usize, String> {
+    usize::from_str_radix(s, 36).map_err(|_| format!("invalid BBQr header `{}`", s))
+}
+
+fn base32_encode(data: &[u8]) -> String {
+    let mut out = String::with_capacity((data.len() * 8).div_ceil(5));
+    let (mut buffer, mut bits) = (0u16, 0);
+    for byte in data {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+
+    out
+}
+
+fn base32_decode(s: &str) -> Result<Vec<u8>, String> {
+    let mut out = Vec::with_capacity(s.len() * 5 / 8);
+    let (mut buffer, mut bits) = (0u16, 0);
+    for c in s.trim_end_matches('=').bytes() {
+        let value = BASE32_ALPHABET
+            .iter()
+            .position(|a| *a == c)
+            .ok_or_else(|| format!("invalid base32 character `{}`", c as char))?;
+        buffer = (buffer << 5) | value as u16;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            out.push((buffer >> bits) as u8);
+        }
+    }
+
+    Ok(out)
+}
+
+pub enum QrViewMsg {
+    Toggle,
+    NextFrame,
+}
+
+#[derive(PartialEq, Properties)]
+pub struct QrViewProps {
+    /// Frames to show. More than one frame are cycled through as an animated QR code
+    pub frames: Vec<String>,
+    #[prop_or_default]
+    pub label: Option<String>,
+    /// Whether the code is visible without clicking on "Show QR code" first
+    #[prop_or(false)]
+    pub shown: bool,
+}
+
+pub struct QrView {
+    shown: bool,
+    frame: usize,
+    interval: Option<Interval>,
+}
+
+impl QrView {
+    fn update_animation(&mut self, ctx: &Context<Self>) {
+        if self.shown && ctx.props().frames.len() > 1 {
+            if self.interval.is_none() {
+                let link = ctx.link().clone();
+                self.interval = Some(Interval::new(FRAME_INTERVAL_MS, move || {
+                    link.send_message(QrViewMsg::NextFrame)
+                }));
+            }
+        } else {
+            self.interval = None;
+        }
+    }
+}
+
+impl Component for QrView {
+    type Message = QrViewMsg;
+    type Properties = QrViewProps;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        let mut qr_view = Self {
+            shown: ctx.props().shown,
+            frame: 0,
+            interval: None,
+        };
+        qr_view.update_animation(ctx);
+        qr_view
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            QrViewMsg::Toggle => {
+                self.shown = !self.shown;
+                self.update_animation(ctx);
+            }
+            QrViewMsg::NextFrame => {
+                self.frame = (self.frame + 1) % ctx.props().frames.len().max(1);
+            }
+        }
+        true
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        self.frame = 0;
+        self.update_animation(ctx);
+        true
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let props = ctx.props();
+        let toggle = ctx.link().callback(|_| QrViewMsg::Toggle);
+
+        let qr_html = match props.frames.get(self.frame).map(|f| qr_svg(f)) {
+            Some(Ok(svg)) => html! {
+                <img class="qr-code" src={format!("data:image/svg+xml;base64,{}", base64::encode(svg))}/>
+            },
+            Some(Err(e)) => html! { <label>{ format!("Can't render QR code: {}", e) }</label> },
+            None => html! {},
+        };
+        let frame_counter = if props.frames.len() > 1 {
+            html! { <div class="qr-frame-counter">{ format!("{}/{}", self.frame + 1, props.frames.len()) }</div> }
+        } else {
+            html! {}
+        };
+
+        html! {
+            <div class="qr-view">
+                if props.shown {
+                    if let Some(label) = &props.label {
+                        <div>{ label }</div>
+                    }
+                } else {
+                    <button type="button" class="btn btn-outline-secondary btn-sm" onclick={toggle}>
+                        <i class="bi bi-qr-code"></i>
+                        { format!(" {} {}", if self.shown { "Hide" } else { "Show" }, props.label.as_deref().unwrap_or("QR code")) }
+                    </button>
+                }
+                if self.shown {
+                    <div>
+                        { qr_html }
+                        { frame_counter }
+                    </div>
+                }
+            </div>
+        }
+    }
+}
+
+pub enum QrImportMsg {
+    FilesSelected(Vec<File>),
+    Decoded(Result<String, String>),
+}
+
+#[derive(PartialEq, Properties)]
+pub struct QrImportProps {
+    /// Called with the payload once every frame has been scanned
+    pub on_import: Callback<Result<Vec<u8>, String>>,
+}
+
+/// File picker decoding QR code images, including multi-part BBQr payloads
+pub struct QrImport {
+    joiner: BbqrJoiner,
+    error: Option<String>,
+}
+
+impl Component for QrImport {
+    type Message = QrImportMsg;
+    type Properties = QrImportProps;
+
+    fn create(_ctx: &Context<Self>) -> Self {
+        Self {
+            joiner: BbqrJoiner::default(),
+            error: None,
+        }
+    }
+
+    fn update(&mut self, ctx: &Context<Self>, msg: Self::Message) -> bool {
+        match msg {
+            QrImportMsg::FilesSelected(files) => {
+                for file in files {
+                    ctx.link().send_future(async move {
+                        let res = gloo_file::futures::read_as_bytes(&file)
+                            .await
+                            .map_err(|e| e.to_string())
+                            .and_then(|bytes| decode_qr_image(&bytes))
+                            .map_err(|e| format!("{}: {}", file.name(), e));
+                        QrImportMsg::Decoded(res)
+                    });
+                }
+                false
+            }
+            QrImportMsg::Decoded(Err(e)) => {
+                self.error = Some(e);
+                true
+            }
+            QrImportMsg::Decoded(Ok(content)) => {
+                self.error = None;
+                if !BbqrJoiner::is_bbqr(&content) {
+                    ctx.props().on_import.emit(Ok(content.into_bytes()));
+                    return true;
+                }
+
+                if let Err(e) = self.joiner.add(&content) {
+                    self.joiner = BbqrJoiner::default();
+                    self.error = Some(e);
+                    return true;
+                }
+                if self.joiner.is_complete() {
+                    ctx.props().on_import.emit(self.joiner.finish());
+                    self.joiner = BbqrJoiner::default();
+                }
+                true
+            }
+        }
+    }
+
+    fn view(&self, ctx: &Context<Self>) -> Html {
+        let onchange = ctx.link().callback(|e: Event| {
+            let input = e.target_unchecked_into::<HtmlInputElement>();
+            let files = files_from_list(input.files());
+            input.set_value("");
+            QrImportMsg::FilesSelected(files)
+        });
+        let (scanned, total) = self.joiner.progress();
+
+        html! {
+            <div class="qr-import">
+                <label class="btn btn-outline-secondary btn-sm">
+                    <i class="bi bi-qr-code-scan"></i>
+                    { " Scan QR code image" }
+                    <input type="file" class="d-none" multiple=true accept="image/png,image/jpeg" {onchange}/>
+                </label>
+                if total > 0 {
+                    <span class="ms-2">{ format!("Scanned {} of {} parts", scanned, total) }</span>
+                }
+                if let Some(e) = &self.error {
+                    <span class="ms-2 text-danger">{ e }</span>
+                }
+            </div>
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn base32_round_trip() {
+        for len in 0..20 {
+            let data = (0..len as u8)
+                .map(|b| b.wrapping_mul(37))
+                .collect::<Vec<_>>();
+            assert_eq!(base32_decode(&base32_encode(&data)).unwrap(), data);
+        }
+        assert_eq!(base32_encode(b"foobar"), "MZXW6YTBOI");
+        assert!(base32_decode("MZXW1").is_err());
+    }
+
+    #[test]
+    fn base36_header() {
+        assert_eq!(base36(0), "00");
+        assert_eq!(base36(35), "0Z");
+        assert_eq!(base36(36 * 36 - 1), "ZZ");
+        assert_eq!(parse_base36("1A").unwrap(), 46);
+        assert!(parse_base36("-1").is_err());
+    }
+
+    #[test]
+    fn bbqr_split_join() {
+        let data = (0..1000).map(|i| (i % 251) as u8).collect::<Vec<_>>();
+        let frames = bbqr_encode(&data, BbqrType::Psbt);
+        assert!(frames.len() > 1);
+        assert!(frames.iter().all(|f| f.starts_with("B$2P")));
+
+        let mut joiner = BbqrJoiner::default();
+        for frame in frames.iter().rev() {
+            assert!(!joiner.is_complete());
+            joiner.add(frame).unwrap();
+        }
+        assert_eq!(joiner.progress(), (frames.len(), frames.len()));
+        assert_eq!(joiner.finish().unwrap(), data);
+    }
+
+    #[test]
+    fn bbqr_single_frame() {
+        let frames = bbqr_encode(b"elephant", BbqrType::UnicodeText);
+        assert_eq!(frames, vec!["B$2U0100MVWGK4DIMFXHI".to_string()]);
+
+        let mut joiner = BbqrJoiner::default();
+        joiner.add(&frames[0]).unwrap();
+        assert_eq!(joiner.finish().unwrap(), b"elephant");
+    }
+
+    #[test]
+    fn bbqr_rejects_bad_frames() {
+        let mut joiner = BbqrJoiner::default();
+        assert!(joiner.add("B$2P01").is_err());
+        assert!(joiner.add("B$ZP0100AAAA").is_err());
+        assert!(joiner.add("B$2P0101AAAA").is_err());
+        assert!(joiner.add("B$€P0100AAAA").is_err());
+        assert!(joiner.add("B$2P01€0AAAA").is_err());
+        assert!(joiner.finish().is_err());
+
+        joiner.add("B$2P0200AAAAAAAA").unwrap();
+        assert!(joiner.add("B$2P0301AAAAAAAA").is_err());
+    }
+}
@@ -0,0 +1,110 @@
+use js_sys::{decode_uri_component, encode_uri_component};
+use wasm_bindgen::JsValue;
+
+use crate::app::Tabs;
+
+/// Prefix of the URL fragment of every route
+const ROUTE_PREFIX: &str = "#/";
+
+/// Tab shown in the URL fragment, like `#/sign?psbt=...`. `#/share?data=...` opens the wallet
+/// creator, which reads the shared wallet from the fragment itself.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Route {
+    pub tab: Tabs,
+    /// Query parameters, decoded
+    params: Vec<(String, String)>,
+}
+
+impl Route {
+    pub fn new(tab: Tabs) -> Self {
+        Route {
+            tab,
+            params: vec![],
+        }
+    }
+
+    /// Parses a URL fragment, returning `None` if it isn't a route of the app
+    pub fn from_fragment(fragment: &str) -> Option<Self> {
+        let route = fragment.strip_prefix(ROUTE_PREFIX)?;
+        let (path, query) = route.split_once('?').unwrap_or((route, ""));
+        let tab = match path {
+            "" | "creator" | "share" => Tabs::KeyManagement,
+            "portfolio" => Tabs::Portfolio,
+            "home" => Tabs::Home,
+            "create" => Tabs::CreateTx,
+            "sign" => Tabs::SignTx,
+            "merge" => Tabs::Merge,
+            _ => return None,
+        };
+        let decode = |s: &str| decode_uri_component(s).ok().map(String::from);
+        let params = query
+            .split('&')
+            .filter(|p| !p.is_empty())
+            .filter_map(|p| {
+                let (name, value) = p.split_once('=').unwrap_or((p, ""));
+                Some((decode(name)?, decode(value)?))
+            })
+            .collect();
+
+        Some(Route { tab, params })
+    }
+
+    pub fn to_fragment(&self) -> String {
+        let path = match self.tab {
+            Tabs::KeyManagement => "creator",
+            Tabs::Portfolio => "portfolio",
+            Tabs::Home => "home",
+            Tabs::CreateTx => "create",
+            Tabs::SignTx => "sign",
+            Tabs::Merge => "merge",
+        };
+        let query = self
+            .params
+            .iter()
+            .map(|(name, value)| {
+                format!(
+                    "{}={}",
+                    encode_uri_component(name),
+                    encode_uri_component(value)
+                )
+            })
+            .collect::<Vec<_>>()
+            .join("&");
+
+        match query.as_str() {
+            "" => format!("{}{}", ROUTE_PREFIX, path),
+            query => format!("{}{}?{}", ROUTE_PREFIX, path, query),
+        }
+    }
+
+    pub fn param(&self, name: &str) -> Option<&str> {
+        self.params
+            .iter()
+            .find(|(n, _)| n == name)
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Route in the address bar
+pub fn current() -> Option<Route> {
+    let hash = web_sys::window().unwrap().location().hash().ok()?;
+    Route::from_fragment(&hash)
+}
+
+/// Shows `route` in the address bar, as a new entry of the browser history unless `replace`
+pub fn navigate(route: &Route, replace: bool) {
+    let window = web_sys::window().unwrap();
+    let history = match window.history() {
+        Ok(history) => history,
+        Err(_) => return,
+    };
+    let url = route.to_fragment();
+    let res = if replace {
+        history.replace_state_with_url(&JsValue::NULL, "", Some(&url))
+    } else {
+        history.push_state_with_url(&JsValue::NULL, "", Some(&url))
+    };
+    if let Err(e) = res {
+        log::warn!("Failed to update the URL: {:?}", e);
+    }
+}
@@ -1,24 +1,54 @@
+use crate::evt::{AppEvent, EventBus};
+use crate::hashlock;
+use crate::psbt_file::{parse_psbt_bytes, parse_psbt_str, PsbtDownload, PsbtImport};
+use crate::qr::{psbt_frames, QrImport, QrView};
 use crate::AppWallet;
 use bdk::bitcoin::util::psbt::PartiallySignedTransaction;
+use bdk::bitcoin::Txid;
 use bdk::wallet::signer::SignOptions;
-use std::str::FromStr;
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
+use yew_agent::{Dispatched, Dispatcher};
 
 pub enum Msg {
     PsbtChanged(InputEvent),
+    PsbtImported(Result<PartiallySignedTransaction, String>),
+    PreimageChanged(InputEvent),
     Sign,
 }
 
 #[derive(Properties, PartialEq)]
 pub struct Props {
     pub wallet: AppWallet,
+    /// PSBT of the transaction in progress to sign
+    #[prop_or_default]
+    pub psbt: Option<PartiallySignedTransaction>,
 }
 
 pub struct Sign {
-    psbt: Option<Result<PartiallySignedTransaction, ()>>,
+    psbt_text: String,
+    psbt: Option<Result<PartiallySignedTransaction, String>>,
     signed_psbt: Option<PartiallySignedTransaction>,
+    preimage: String,
+    preimages_added: Option<usize>,
     wallet: AppWallet,
+    dispatcher: Dispatcher<EventBus>,
+}
+
+impl Sign {
+    fn load(&mut self, psbt: PartiallySignedTransaction) {
+        self.signed_psbt = None;
+        self.preimages_added = None;
+        self.psbt_text = psbt.to_string();
+        self.psbt = Some(Ok(psbt));
+    }
+
+    fn loaded_txid(&self) -> Option<Txid> {
+        match &self.psbt {
+            Some(Ok(psbt)) => Some(psbt.unsigned_tx.txid()),
+            _ => None,
+        }
+    }
 }
 
 impl Component for Sign {
@@ -27,30 +57,72 @@ impl Component for Sign {
 
     fn create(ctx: &Context<Self>) -> Self {
         let props = ctx.props();
-        Self {
+        let mut sign = Self {
             wallet: props.wallet.clone(),
+            psbt_text: String::new(),
             psbt: None,
             signed_psbt: None,
+            preimage: String::new(),
+            preimages_added: None,
+            dispatcher: EventBus::dispatcher(),
+        };
+        if let Some(psbt) = &props.psbt {
+            sign.load(psbt.clone());
+        }
+
+        sign
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        // The PSBT in the workspace also changes when it's signed here, only load another
+        // transaction
+        match &ctx.props().psbt {
+            Some(psbt) if Some(psbt.unsigned_tx.txid()) != self.loaded_txid() => {
+                self.load(psbt.clone());
+                true
+            }
+            _ => false,
         }
     }
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let oninput = ctx.link().callback(|e: InputEvent| Msg::PsbtChanged(e));
         let onclick = ctx.link().callback(|_| Msg::Sign);
-        let (is_invalid, button_disabled) = match self.psbt {
-            Some(Ok(_)) => ("", false),
-            Some(Err(_)) => ("is-invalid", true),
-            None => ("", true),
+        let on_import = ctx.link().callback(Msg::PsbtImported);
+        let on_preimage = ctx.link().callback(Msg::PreimageChanged);
+        let on_qr_import = ctx.link().callback(|res: Result<Vec<u8>, String>| {
+            Msg::PsbtImported(res.and_then(|b| parse_psbt_bytes(&b)))
+        });
+        let (is_invalid, button_disabled, psbt_error) = match &self.psbt {
+            Some(Ok(_)) => ("", false, ""),
+            Some(Err(e)) => ("is-invalid", true, e.as_str()),
+            None => ("", true, ""),
         };
+        let is_watch_only = self.wallet.is_watch_only();
+        let has_preimage = !self.preimage.trim().is_empty();
         html! {
             <div class="daniela">
+                if is_watch_only {
+                    <div class="alert alert-warning">{ "This wallet is watch-only: Elephant doesn't know any of its private keys, so it can only add hash-lock preimages" }</div>
+                }
                 <label for="psbtTextArea" class="form-label">{"Paste here your PSBT:"}</label>
-                <textarea id="psbtTextArea" class={classes!("form-control", "daniela-textarea", is_invalid).to_string()} rows="5" {oninput}></textarea>
+                <textarea id="psbtTextArea" class={classes!("form-control", "daniela-textarea", is_invalid).to_string()} rows="5" {oninput} value={self.psbt_text.clone()}></textarea>
                 <div class="invalid-feedback">
-                { "Please enter a valid PSBT" }
+                { format!("Invalid PSBT: {}", psbt_error) }
                 </div>
-                <button class="btn btn-primary" {onclick} disabled={ button_disabled }>{ "Sign" }</button>
+                <PsbtImport {on_import}/>
+                <QrImport on_import={on_qr_import}/>
+                <label for="preimageInput" class="form-label">{"Hash-lock secret or preimage (optional):"}</label>
+                <input id="preimageInput" class="form-control" type="text" placeholder="Secret used in a hash-lock block, or a 32-byte hex preimage" oninput={on_preimage} value={self.preimage.clone()}/>
+                <button class="btn btn-primary" {onclick} disabled={ button_disabled || (is_watch_only && !has_preimage) }>{ "Sign" }</button>
+                if let Some(added) = self.preimages_added {
+                    <div class="alert alert-info">{ format!("Added {} preimage(s) to the PSBT", added) }</div>
+                }
                 <textarea class="form-control daniela-textarea" id="signed-psbt-textarea" rows="10" readonly=true value={self.signed_psbt.as_ref().map(|s| s.to_string())}></textarea>
+                if let Some(psbt) = &self.signed_psbt {
+                    <PsbtDownload psbt={psbt.clone()} name="signed"/>
+                    <QrView frames={psbt_frames(psbt)}/>
+                }
             </div>
         }
     }
@@ -64,23 +136,41 @@ impl Component for Sign {
                 if psbt == "" {
                     self.psbt = None;
                 } else {
-                    self.psbt = Some(PartiallySignedTransaction::from_str(&psbt).map_err(|_| ()));
+                    self.psbt = Some(parse_psbt_str(&psbt));
                 }
+                self.psbt_text = psbt;
                 log::info!("Psbt parsing: {:?}", self.psbt);
                 true
             }
+            Msg::PsbtImported(res) => {
+                log::info!("Psbt imported: {:?}", res);
+                self.signed_psbt = None;
+                self.psbt_text = res.as_ref().map(|p| p.to_string()).unwrap_or_default();
+                self.psbt = Some(res);
+                true
+            }
+
+            Msg::PreimageChanged(e) => {
+                self.preimage = e.target_unchecked_into::<HtmlInputElement>().value();
+                self.preimages_added = None;
+                true
+            }
 
             Msg::Sign => {
                 log::info!("Sign");
+                let psbt = self.psbt.as_mut().unwrap().as_mut().unwrap();
+                self.preimages_added = if self.preimage.trim().is_empty() {
+                    None
+                } else {
+                    Some(hashlock::add_preimage(psbt, &self.preimage))
+                };
                 self.wallet
                     .borrow()
-                    .0
-                    .sign(
-                        &mut self.psbt.as_mut().unwrap().as_mut().unwrap(),
-                        SignOptions::default(),
-                    )
+                    .sign(psbt, SignOptions::default())
                     .unwrap();
-                self.signed_psbt = Some(self.psbt.as_ref().unwrap().as_ref().unwrap().clone());
+                let signed = self.psbt.as_ref().unwrap().as_ref().unwrap().clone();
+                self.dispatcher.send(AppEvent::PsbtSigned(signed.clone()));
+                self.signed_psbt = Some(signed);
                 true
             }
         }
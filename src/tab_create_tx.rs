@@ -2,19 +2,27 @@ use std::str::FromStr;
 
 use bdk::{
     bitcoin::{psbt::Psbt, Address},
-    descriptor::policy::SatisfiableItem,
+    descriptor::policy::{PkOrF, SatisfiableItem},
+    descriptor::Policy,
     FeeRate, KeychainKind, TransactionDetails,
 };
 use web_sys::HtmlInputElement;
 use yew::prelude::*;
+use yew_agent::Dispatched;
 
+use crate::app::UNSPENDABLE_KEY;
+use crate::evt::{AppEvent, EventBus};
 use crate::policy_view::{PolicyView, Selection};
+use crate::psbt_file::PsbtDownload;
+use crate::qr::{address_from_bip21, psbt_frames, QrImport, QrView};
 use crate::AppWallet;
 
 pub enum CreateTxMsg {
     CreateButtonClicked,
     AddressInputEvent(InputEvent),
+    AddressScanned(Result<Vec<u8>, String>),
     AmountInputEvent(InputEvent),
+    Created(Box<Result<(Psbt, TransactionDetails), bdk::Error>>),
 }
 
 #[derive(PartialEq, Properties)]
@@ -27,6 +35,15 @@ pub struct TabCreateTx {
     amount: u64,
     policy_selection: Selection,
     psbt_result: Option<Result<(Psbt, TransactionDetails), bdk::Error>>,
+    /// The transaction is built once the wallet finishes syncing
+    is_waiting: bool,
+}
+
+fn is_unspendable(policy: &Policy) -> bool {
+    matches!(
+        &policy.item,
+        SatisfiableItem::SchnorrSignature(PkOrF::XOnlyPubkey(pk)) if pk.to_string() == UNSPENDABLE_KEY
+    )
 }
 
 impl Component for TabCreateTx {
@@ -39,6 +56,7 @@ impl Component for TabCreateTx {
             amount: 0,
             policy_selection: Selection::default(),
             psbt_result: None,
+            is_waiting: false,
         }
     }
 
@@ -46,8 +64,6 @@ impl Component for TabCreateTx {
         match msg {
             CreateTxMsg::CreateButtonClicked => {
                 let props = ctx.props();
-                let (wallet, _) = &mut *props.wallet.borrow_mut();
-
                 let spk = match Address::from_str(&self.addr) {
                     Ok(addr) => addr.script_pubkey(),
                     Err(err) => {
@@ -59,21 +75,46 @@ impl Component for TabCreateTx {
                     }
                 };
 
-                let mut builder = wallet.build_tx();
-
-                builder
-                    .add_recipient(spk, self.amount)
-                    .fee_rate(FeeRate::from_sat_per_vb(1.0))
-                    .policy_path(self.policy_selection.extract(), KeychainKind::External)
-                    .enable_rbf();
-
-                self.psbt_result = Some(builder.finish());
+                let amount = self.amount;
+                let policy_path = self.policy_selection.extract();
+                let link = ctx.link().clone();
+                self.is_waiting = props.wallet.is_syncing();
+                props.wallet.mutate(move |wallet| {
+                    let mut builder = wallet.build_tx();
+
+                    builder
+                        .add_recipient(spk, amount)
+                        .fee_rate(FeeRate::from_sat_per_vb(1.0))
+                        .policy_path(policy_path, KeychainKind::External)
+                        .enable_rbf();
+
+                    // The tab may have been closed while the wallet was syncing, the other
+                    // components receive the PSBT anyway
+                    let res = builder.finish();
+                    if let Ok((psbt, _)) = &res {
+                        EventBus::dispatcher().send(AppEvent::PsbtCreated(psbt.clone()));
+                    }
+                    link.send_message(CreateTxMsg::Created(Box::new(res)));
+                });
+                true
+            }
+            CreateTxMsg::Created(res) => {
+                self.is_waiting = false;
+                self.psbt_result = Some(*res);
                 true
             }
             CreateTxMsg::AddressInputEvent(e) => {
                 self.addr = e.target_unchecked_into::<HtmlInputElement>().value();
                 true
             }
+            CreateTxMsg::AddressScanned(Ok(bytes)) => {
+                self.addr = address_from_bip21(&String::from_utf8_lossy(&bytes));
+                true
+            }
+            CreateTxMsg::AddressScanned(Err(e)) => {
+                log::warn!("Failed to scan address: {}", e);
+                false
+            }
             CreateTxMsg::AmountInputEvent(e) => {
                 self.amount = e
                     .target_unchecked_into::<HtmlInputElement>()
@@ -87,26 +128,29 @@ impl Component for TabCreateTx {
 
     fn view(&self, ctx: &Context<Self>) -> Html {
         let props = ctx.props();
-        let (wallet, _) = &*props.wallet.borrow();
+        let wallet = props.wallet.borrow();
 
         let policy = wallet.policies(KeychainKind::External).unwrap().unwrap();
         let first_id = policy.item.id();
-        let policy = match policy.item {
-            SatisfiableItem::Thresh { items, threshold } => {
-                assert_eq!(threshold, 1);
-                assert_eq!(items.len(), 2);
+        let policy_selection = self.policy_selection.clone();
+        // Hide the key path spend of wallets compiled from a policy, as it uses an unspendable key
+        let policy = match &policy.item {
+            SatisfiableItem::Thresh {
+                items,
+                threshold: 1,
+            } if items.len() == 2 && is_unspendable(&items[0]) => {
+                policy_selection.select(first_id, 1);
                 items[1].clone()
             }
-            _ => panic!(""),
+            _ => policy,
         };
-        let policy_selection = self.policy_selection.clone();
-        policy_selection.select(first_id, 1);
 
         let onclick_create_button = ctx.link().callback(|_| CreateTxMsg::CreateButtonClicked);
         let oninput_address = ctx.link().callback(|e| CreateTxMsg::AddressInputEvent(e));
         let oninput_amount = ctx.link().callback(|e| CreateTxMsg::AmountInputEvent(e));
+        let on_address_scanned = ctx.link().callback(CreateTxMsg::AddressScanned);
 
-        let disabled_create_button = self.addr == "" || self.amount == 0;
+        let disabled_create_button = self.addr == "" || self.amount == 0 || self.is_waiting;
 
         let result_html = match &self.psbt_result {
             Some(Ok((psbt, details))) => html! {
@@ -114,6 +158,8 @@ impl Component for TabCreateTx {
                     <label>{ format!("PSBT Created with txid: {}", details.txid) }</label>
                     <br/>
                     <textarea class="form-control daniela-textarea" rows="10" readonly=true value={ psbt.to_string() }></textarea>
+                    <PsbtDownload psbt={psbt.clone()} name={format!("{}-unsigned", details.txid)}/>
+                    <QrView frames={psbt_frames(psbt)}/>
                 </div>
             },
             Some(Err(err)) => html! {
@@ -124,15 +170,19 @@ impl Component for TabCreateTx {
 
         html! {
             <div class = "daniela" >
+                if props.wallet.is_watch_only() {
+                    <div class="alert alert-info">{ "This wallet is watch-only: the PSBT will be unsigned, sign it with the wallets holding the keys and merge the signatures in the \"Merge and broadcast\" tab" }</div>
+                }
                 <label>{ "Destination Address: " }</label>
-                <input type="text" class="form-control" oninput={oninput_address}/>
+                <input type="text" class="form-control" oninput={oninput_address} value={self.addr.clone()}/>
+                <QrImport on_import={on_address_scanned}/>
                 <br/>
                 <label>{ "Amount (sats): " }</label>
                 <input type="number" class="form-control" oninput={oninput_amount}/>
                 <br/>
                 <PolicyView selection={policy_selection} node={policy}/>
                 <br/>
-                <button class="btn btn-primary" disabled={disabled_create_button} onclick={onclick_create_button}> { "Create" } </button>
+                <button class="btn btn-primary" disabled={disabled_create_button} onclick={onclick_create_button}> { if self.is_waiting { "Waiting for the sync..." } else { "Create" } } </button>
                 <br/>
                 { result_html }
             </div>
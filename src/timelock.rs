@@ -0,0 +1,339 @@
+/// Maximum value of a BIP68 relative timelock, both in blocks and in 512-second units
+pub const MAX_RELATIVE: u32 = 0xffff;
+/// `nLockTime` values below this are block heights, values above are UNIX timestamps
+pub const LOCKTIME_THRESHOLD: u32 = 500_000_000;
+
+const SEQUENCE_TYPE_FLAG: u32 = 1 << 22;
+const SEQUENCE_UNIT_SECS: u32 = 512;
+const SEQUENCE_DISABLE_FLAG: u32 = 1 << 31;
+const SEQUENCE_VALUE_MASK: u32 = 0xffff;
+
+/// Units of the relative time-based timelock block
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TimeUnit {
+    Minutes,
+    Hours,
+    Days,
+    Weeks,
+}
+
+impl TimeUnit {
+    /// Largest first, so that `older_time_to_duration` picks the most readable unit
+    pub fn all() -> [TimeUnit; 4] {
+        [
+            TimeUnit::Weeks,
+            TimeUnit::Days,
+            TimeUnit::Hours,
+            TimeUnit::Minutes,
+        ]
+    }
+
+    /// Name used in the Blockly dropdown
+    pub fn name(&self) -> &'static str {
+        match self {
+            TimeUnit::Minutes => "minutes",
+            TimeUnit::Hours => "hours",
+            TimeUnit::Days => "days",
+            TimeUnit::Weeks => "weeks",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        TimeUnit::all().into_iter().find(|u| u.name() == name)
+    }
+
+    pub fn seconds(&self) -> u64 {
+        match self {
+            TimeUnit::Minutes => 60,
+            TimeUnit::Hours => 60 * 60,
+            TimeUnit::Days => 24 * 60 * 60,
+            TimeUnit::Weeks => 7 * 24 * 60 * 60,
+        }
+    }
+}
+
+/// Validates a relative timelock expressed in blocks
+pub fn older_blocks(blocks: u32) -> Result<u32, String> {
+    if blocks == 0 || blocks > MAX_RELATIVE {
+        return Err(format!(
+            "relative timelocks must be between 1 and {} blocks",
+            MAX_RELATIVE
+        ));
+    }
+
+    Ok(blocks)
+}
+
+/// Converts a duration to the BIP68 encoding of a time-based relative timelock. The duration is
+/// rounded up to the next multiple of 512 seconds, so that the lock is never shorter than asked.
+pub fn older_time(value: u32, unit: TimeUnit) -> Result<u32, String> {
+    let seconds = value as u64 * unit.seconds();
+    let intervals = seconds.div_ceil(SEQUENCE_UNIT_SECS as u64);
+    if intervals == 0 || intervals > MAX_RELATIVE as u64 {
+        return Err(format!(
+            "relative timelocks must be between 512 seconds and {}",
+            format_duration(MAX_RELATIVE as u64 * SEQUENCE_UNIT_SECS as u64)
+        ));
+    }
+
+    Ok(SEQUENCE_TYPE_FLAG | intervals as u32)
+}
+
+/// Whether a consensus-encoded `nSequence` is a time-based relative timelock
+pub fn is_older_time(sequence: u32) -> bool {
+    sequence & SEQUENCE_DISABLE_FLAG == 0 && sequence & SEQUENCE_TYPE_FLAG != 0
+}
+
+/// Inverse of `older_time`: returns the value in the largest unit that encodes back to the same
+/// number of 512-second intervals
+pub fn older_time_to_duration(sequence: u32) -> (u32, TimeUnit) {
+    let intervals = (sequence & SEQUENCE_VALUE_MASK) as u64;
+    let seconds = intervals * SEQUENCE_UNIT_SECS as u64;
+
+    TimeUnit::all()
+        .into_iter()
+        .map(|unit| ((seconds / unit.seconds()) as u32, unit))
+        .find(|(value, unit)| *value > 0 && older_time(*value, *unit) == Ok(sequence))
+        .unwrap_or(((seconds / 60) as u32, TimeUnit::Minutes))
+}
+
+/// Validates an absolute timelock expressed as a block height
+pub fn after_height(height: u32) -> Result<u32, String> {
+    if height == 0 || height >= LOCKTIME_THRESHOLD {
+        return Err(format!(
+            "block heights must be between 1 and {}",
+            LOCKTIME_THRESHOLD - 1
+        ));
+    }
+
+    Ok(height)
+}
+
+/// Parses a UTC date (`YYYY-MM-DD`, optionally followed by `HH:MM` or `HH:MM:SS`) into the
+/// `nLockTime` encoding of a timestamp-based absolute timelock
+pub fn after_time(date: &str) -> Result<u32, String> {
+    let invalid = || format!("`{}` is not a valid date, use YYYY-MM-DD HH:MM", date);
+
+    let date = date.trim();
+    let (day, time) = date.split_once(' ').unwrap_or((date, "00:00"));
+    let day = day
+        .split('-')
+        .map(|p| p.parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+    let time = time
+        .trim()
+        .split(':')
+        .map(|p| p.parse::<u32>().map_err(|_| invalid()))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    let (year, month, day) = match day[..] {
+        [y, m, d] if (1..=12).contains(&m) && d >= 1 && d <= days_in_month(y, m) => (y, m, d),
+        _ => return Err(invalid()),
+    };
+    let (hours, minutes, seconds) = match time[..] {
+        [h, m] => (h, m, 0),
+        [h, m, s] => (h, m, s),
+        _ => return Err(invalid()),
+    };
+    if hours > 23 || minutes > 59 || seconds > 59 {
+        return Err(invalid());
+    }
+
+    let timestamp =
+        days_from_civil(year, month, day) * 86_400 + (hours * 3600 + minutes * 60 + seconds) as i64;
+    if timestamp < LOCKTIME_THRESHOLD as i64 || timestamp > u32::MAX as i64 {
+        return Err(format!(
+            "dates must be between {} and {}",
+            format_timestamp(LOCKTIME_THRESHOLD),
+            format_timestamp(u32::MAX)
+        ));
+    }
+
+    Ok(timestamp as u32)
+}
+
+/// Formats a UNIX timestamp as a UTC date accepted by `after_time`, like `YYYY-MM-DD HH:MM`.
+/// Seconds are only shown when they aren't zero.
+pub fn format_timestamp(timestamp: u32) -> String {
+    let (year, month, day) = civil_from_days(timestamp as i64 / 86_400);
+    let secs = timestamp % 86_400;
+    let (hours, minutes, seconds) = (secs / 3600, secs % 3600 / 60, secs % 60);
+
+    match seconds {
+        0 => format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}",
+            year, month, day, hours, minutes
+        ),
+        _ => format!(
+            "{:04}-{:02}-{:02} {:02}:{:02}:{:02}",
+            year, month, day, hours, minutes, seconds
+        ),
+    }
+}
+
+/// Human-readable approximation of a duration, e.g. "2 days 4 hours"
+pub fn format_duration(seconds: u64) -> String {
+    let mut parts = vec![];
+    let mut remaining = seconds;
+    for unit in TimeUnit::all() {
+        let n = remaining / unit.seconds();
+        if n > 0 && parts.len() < 2 {
+            parts.push(format!(
+                "{} {}",
+                n,
+                if n == 1 {
+                    unit.name().trim_end_matches('s')
+                } else {
+                    unit.name()
+                }
+            ));
+            remaining -= n * unit.seconds();
+        }
+    }
+
+    if parts.is_empty() {
+        format!("{} seconds", seconds)
+    } else {
+        parts.join(" ")
+    }
+}
+
+/// Describes a consensus-encoded relative timelock
+pub fn describe_older(sequence: u32) -> String {
+    let value = sequence & SEQUENCE_VALUE_MASK;
+    if is_older_time(sequence) {
+        format!(
+            "{} ({} × 512 seconds)",
+            format_duration(value as u64 * SEQUENCE_UNIT_SECS as u64),
+            value
+        )
+    } else {
+        format!("{} block(s)", value)
+    }
+}
+
+/// Describes a consensus-encoded absolute timelock
+pub fn describe_after(locktime: u32) -> String {
+    if locktime < LOCKTIME_THRESHOLD {
+        format!("block height {}", locktime)
+    } else {
+        format!("{} UTC", format_timestamp(locktime))
+    }
+}
+
+fn is_leap_year(year: u32) -> bool {
+    (year.is_multiple_of(4) && !year.is_multiple_of(100)) || year.is_multiple_of(400)
+}
+
+fn days_in_month(year: u32, month: u32) -> u32 {
+    match month {
+        2 if is_leap_year(year) => 29,
+        2 => 28,
+        4 | 6 | 9 | 11 => 30,
+        _ => 31,
+    }
+}
+
+// Days since 1970-01-01, from http://howardhinnant.github.io/date_algorithms.html
+fn days_from_civil(year: u32, month: u32, day: u32) -> i64 {
+    let y = year as i64 - (month <= 2) as i64;
+    let era = y.div_euclid(400);
+    let yoe = y - era * 400;
+    let m = month as i64;
+    let doy = (153 * (if m > 2 { m - 3 } else { m + 9 }) + 2) / 5 + day as i64 - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+
+    era * 146_097 + doe - 719_468
+}
+
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719_468;
+    let era = z.div_euclid(146_097);
+    let doe = z - era * 146_097;
+    let yoe = (doe - doe / 1460 + doe / 36_524 - doe / 146_096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + (month <= 2) as i64;
+
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn relative_time_encoding() {
+        assert_eq!(older_time(1, TimeUnit::Hours), Ok(SEQUENCE_TYPE_FLAG | 8));
+        assert_eq!(
+            older_time(512, TimeUnit::Minutes),
+            Ok(SEQUENCE_TYPE_FLAG | 60)
+        );
+        assert!(older_time(0, TimeUnit::Days).is_err());
+        assert!(older_time(389, TimeUnit::Days).is_err());
+
+        assert!(is_older_time(SEQUENCE_TYPE_FLAG | 8));
+        assert!(!is_older_time(144));
+        assert!(!is_older_time(
+            SEQUENCE_DISABLE_FLAG | SEQUENCE_TYPE_FLAG | 8
+        ));
+
+        for unit in [TimeUnit::Weeks, TimeUnit::Days, TimeUnit::Hours] {
+            let sequence = older_time(3, unit).unwrap();
+            assert_eq!(older_time_to_duration(sequence), (3, unit));
+        }
+        // 3 minutes are rounded up to a single 512-second interval
+        let sequence = older_time(3, TimeUnit::Minutes).unwrap();
+        assert_eq!(sequence, SEQUENCE_TYPE_FLAG | 1);
+        assert_eq!(older_time_to_duration(sequence), (8, TimeUnit::Minutes));
+    }
+
+    #[test]
+    fn relative_blocks_and_heights() {
+        assert_eq!(older_blocks(144), Ok(144));
+        assert!(older_blocks(0).is_err());
+        assert!(older_blocks(MAX_RELATIVE + 1).is_err());
+
+        assert_eq!(after_height(700_000), Ok(700_000));
+        assert!(after_height(0).is_err());
+        assert!(after_height(LOCKTIME_THRESHOLD).is_err());
+    }
+
+    #[test]
+    fn absolute_time_encoding() {
+        assert_eq!(after_time("2020-01-01"), Ok(1_577_836_800));
+        assert_eq!(after_time("2020-01-01 00:00"), Ok(1_577_836_800));
+        assert_eq!(after_time(" 2024-02-29 12:30:15 "), Ok(1_709_209_815));
+        assert!(after_time("2023-02-29").is_err());
+        assert!(after_time("2020-01-01 24:00").is_err());
+        assert!(after_time("1980-01-01").is_err());
+        assert!(after_time("2107-01-01").is_err());
+        assert!(after_time("yesterday").is_err());
+    }
+
+    #[test]
+    fn timestamps_round_trip() {
+        assert_eq!(format_timestamp(1_577_836_800), "2020-01-01 00:00");
+        assert_eq!(format_timestamp(1_709_209_800), "2024-02-29 12:30");
+        assert_eq!(format_timestamp(1_709_209_815), "2024-02-29 12:30:15");
+        assert_eq!(format_timestamp(LOCKTIME_THRESHOLD), "1985-11-05 00:53:20");
+        assert_eq!(format_timestamp(u32::MAX), "2106-02-07 06:28:15");
+
+        for timestamp in [LOCKTIME_THRESHOLD, 1_577_836_800, 1_709_209_815, u32::MAX] {
+            assert_eq!(after_time(&format_timestamp(timestamp)), Ok(timestamp));
+        }
+    }
+
+    #[test]
+    fn descriptions() {
+        assert_eq!(describe_older(144), "144 block(s)");
+        assert_eq!(
+            describe_older(SEQUENCE_TYPE_FLAG | 675),
+            "4 days (675 × 512 seconds)"
+        );
+        assert_eq!(describe_after(700_000), "block height 700000");
+        assert_eq!(describe_after(1_577_836_800), "2020-01-01 00:00 UTC");
+    }
+}
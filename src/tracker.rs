@@ -0,0 +1,82 @@
+use bdk::bitcoin::Txid;
+use bdk::blockchain::{esplora::EsploraError, EsploraBlockchain};
+
+/// Number of confirmations after which we stop polling for a transaction
+pub const TRACK_CONFIRMATIONS: u32 = 6;
+/// How often tracked transactions are polled, in milliseconds
+pub const POLL_INTERVAL_MS: u32 = 30_000;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TxState {
+    /// We haven't heard back from the backend yet
+    Unknown,
+    Mempool,
+    Confirmed {
+        height: u32,
+    },
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct TrackedTx {
+    pub txid: Txid,
+    pub state: TxState,
+}
+
+impl TrackedTx {
+    pub fn new(txid: Txid) -> Self {
+        TrackedTx {
+            txid,
+            state: TxState::Unknown,
+        }
+    }
+
+    pub fn confirmations(&self, tip: u32) -> u32 {
+        match self.state {
+            TxState::Confirmed { height } if tip >= height => tip - height + 1,
+            _ => 0,
+        }
+    }
+
+    pub fn is_done(&self, tip: u32) -> bool {
+        self.confirmations(tip) >= TRACK_CONFIRMATIONS
+    }
+
+    pub fn description(&self, tip: u32) -> String {
+        match self.state {
+            TxState::Unknown => format!("Transaction {} was broadcast", self.txid),
+            TxState::Mempool => format!("Transaction {} is in the mempool", self.txid),
+            TxState::Confirmed { .. } => format!(
+                "Transaction {} is confirmed ({} confirmations)",
+                self.txid,
+                self.confirmations(tip)
+            ),
+        }
+    }
+}
+
+/// Result of polling the backend: the current tip and the state of every transaction asked for
+pub struct PollResult {
+    pub tip: u32,
+    pub states: Vec<(Txid, TxState)>,
+}
+
+pub async fn poll(
+    blockchain: &EsploraBlockchain,
+    txids: Vec<Txid>,
+) -> Result<PollResult, EsploraError> {
+    let tip = blockchain.get_height().await?;
+    let mut states = Vec::with_capacity(txids.len());
+    for txid in txids {
+        let state = match blockchain.get_tx_status(&txid).await? {
+            Some(status) if status.confirmed => match status.block_height {
+                Some(height) => TxState::Confirmed { height },
+                None => TxState::Mempool,
+            },
+            Some(_) => TxState::Mempool,
+            None => TxState::Unknown,
+        };
+        states.push((txid, state));
+    }
+
+    Ok(PollResult { tip, states })
+}
@@ -0,0 +1,496 @@
+use std::str::FromStr;
+
+use bdk::miniscript::{Descriptor, TranslatePk, Translator};
+use bdk::KeychainKind;
+use gloo_file::{Blob, ObjectUrl};
+use serde_json::{json, Value};
+use yew::prelude::*;
+
+use crate::app::UNSPENDABLE_KEY;
+use crate::keymanager::keys::with_origin;
+use crate::AppWallet;
+
+/// Derivation of the keys used to receive, swapped with `CHANGE_PATH` to get the change descriptor
+const RECEIVE_PATH: &str = "/0/*";
+const CHANGE_PATH: &str = "/1/*";
+/// Suffixes of BIP-388 key placeholders, both meaning `/0/*` for receiving and `/1/*` for change
+const BIP388_SUFFIXES: &[&str] = &["/**", "/<0;1>/*"];
+
+/// Public descriptors of a wallet, in the formats understood by other wallets
+#[derive(Debug, Clone, PartialEq)]
+pub struct WalletExport {
+    pub name: String,
+    /// Receiving descriptor, with checksum
+    pub external: String,
+    /// Change descriptor, with checksum. Same as `external` when the keys don't have a change path.
+    pub internal: String,
+}
+
+impl WalletExport {
+    pub fn new(wallet: &AppWallet, name: &str) -> Result<Self, String> {
+        let public = |keychain| {
+            wallet
+                .borrow()
+                .public_descriptor(keychain)
+                .map_err(|e| e.to_string())
+        };
+        let external = public(KeychainKind::External)?.ok_or("The wallet has no descriptor")?;
+        let internal = public(KeychainKind::Internal)?.unwrap_or_else(|| external.clone());
+
+        // Miniscript drops the origin of single keys, add it back for the other wallets. Nobody
+        // owns the unspendable key, so it has no origin.
+        let with_origins = |d: &Descriptor<_>| {
+            map_keys(&d.to_string(), |k| {
+                Ok(match k {
+                    UNSPENDABLE_KEY => k.to_string(),
+                    k => with_origin(k),
+                })
+            })
+            .map(|d| d.to_string())
+        };
+
+        Ok(WalletExport {
+            name: name.to_string(),
+            external: with_origins(&external)?,
+            internal: with_origins(&internal)?,
+        })
+    }
+
+    fn is_ranged(&self) -> bool {
+        self.external.contains('*')
+    }
+
+    /// Argument of Bitcoin Core's `importdescriptors`
+    pub fn bitcoin_core(&self) -> String {
+        let entry = |desc: &str, internal: bool| {
+            let mut entry = json!({
+                "desc": desc,
+                "timestamp": "now",
+                "active": self.is_ranged(),
+                "internal": internal,
+            });
+            if self.is_ranged() {
+                entry["range"] = json!([0, 999]);
+            }
+            if !internal {
+                entry["label"] = self.name.clone().into();
+            }
+            entry
+        };
+
+        let mut entries = vec![entry(&self.external, false)];
+        if self.internal != self.external {
+            entries.push(entry(&self.internal, true));
+        }
+
+        serde_json::to_string_pretty(&entries).unwrap()
+    }
+
+    /// Wallet file in the format exported by Specter, which Sparrow can import as well
+    pub fn specter(&self) -> String {
+        serde_json::to_string_pretty(&json!({
+            "label": self.name,
+            "blockheight": 0,
+            "descriptor": self.external,
+            "devices": [],
+        }))
+        .unwrap()
+    }
+
+    /// BIP-388 wallet policy: the descriptor template, with keys replaced by `@i` placeholders,
+    /// and the list of keys.
+    ///
+    /// Single keys (like the internal key of compiled policies) aren't part of BIP-388, they are
+    /// kept as `@i` without derivation: only signers accepting them can register the policy.
+    pub fn bip388(&self) -> Result<String, String> {
+        let mut keys_info: Vec<String> = vec![];
+        let template = map_keys(&self.external, |key| {
+            let (key, suffix) = match key.strip_suffix(RECEIVE_PATH) {
+                Some(key) => (key, BIP388_SUFFIXES[0]),
+                None if key.contains('*') => {
+                    return Err(format!(
+                        "`{}` doesn't end with `{}`, it can't be used in a BIP-388 policy",
+                        key, RECEIVE_PATH
+                    ))
+                }
+                None => (key, ""),
+            };
+            let index = match keys_info.iter().position(|k| k == key) {
+                Some(index) => index,
+                None => {
+                    keys_info.push(key.to_string());
+                    keys_info.len() - 1
+                }
+            };
+
+            Ok(format!("@{}{}", index, suffix))
+        })?;
+
+        Ok(serde_json::to_string_pretty(&json!({
+            "name": self.name,
+            "descriptor_template": format!("{:#}", template),
+            "keys_info": keys_info,
+        }))
+        .unwrap())
+    }
+}
+
+/// Returns the change descriptor of a wallet, using `/1/*` instead of `/0/*` for every key.
+/// Returns `None` if no key has a receiving path, the wallet then sends change to itself.
+pub fn change_descriptor(descriptor: &str) -> Option<String> {
+    let mut changed = false;
+    let change = map_keys(descriptor, |key| {
+        Ok(match key.strip_suffix(RECEIVE_PATH) {
+            Some(base) => {
+                changed = true;
+                format!("{}{}", base, CHANGE_PATH)
+            }
+            None => key.to_string(),
+        })
+    })
+    .ok()?;
+
+    changed.then(|| change.to_string())
+}
+
+/// Reads the descriptor of a wallet exported by Elephant or another wallet: Bitcoin Core's
+/// `importdescriptors` JSON, a Specter/Sparrow wallet file or a BIP-388 wallet policy.
+///
+/// Returns `None` if `input` isn't JSON, it may then be a plain descriptor.
+pub fn import_wallet_file(input: &str) -> Option<Result<String, String>> {
+    let value = serde_json::from_str::<Value>(input.trim()).ok()?;
+    let res = match &value {
+        Value::Array(entries) => entries
+            .iter()
+            .find(|e| e["internal"] != true)
+            .and_then(|e| e["desc"].as_str())
+            .map(str::to_string)
+            .ok_or_else(|| "No receiving descriptor in the Bitcoin Core export".to_string()),
+        Value::Object(map) if map.contains_key("descriptor_template") => from_bip388(&value),
+        Value::Object(map) => map
+            .get("descriptor")
+            .and_then(Value::as_str)
+            .map(str::to_string)
+            .ok_or_else(|| "No descriptor in the wallet file".to_string()),
+        _ => Err("Not a wallet file".to_string()),
+    };
+
+    Some(res)
+}
+
+fn from_bip388(policy: &Value) -> Result<String, String> {
+    let template = policy["descriptor_template"]
+        .as_str()
+        .ok_or("The descriptor template is missing")?;
+    let keys_info = policy["keys_info"]
+        .as_array()
+        .ok_or("The list of keys is missing")?;
+
+    let descriptor = map_keys(template, |placeholder| {
+        let (index, suffix) = placeholder
+            .strip_prefix('@')
+            .map(|p| p.split_at(p.find('/').unwrap_or(p.len())))
+            .ok_or_else(|| format!("`{}` is not a key placeholder", placeholder))?;
+        let key = index
+            .parse::<usize>()
+            .ok()
+            .and_then(|i| keys_info.get(i))
+            .and_then(Value::as_str)
+            .ok_or_else(|| format!("Unknown key `{}`", placeholder))?;
+
+        match suffix {
+            "" => Ok(key.to_string()),
+            s if BIP388_SUFFIXES.contains(&s) => Ok(format!("{}{}", key, RECEIVE_PATH)),
+            s => Err(format!(
+                "Unsupported derivation `{}` for `{}`",
+                s, placeholder
+            )),
+        }
+    })?;
+
+    Ok(descriptor.to_string())
+}
+
+/// Replaces every key of a descriptor with the result of `f`
+fn map_keys<F>(descriptor: &str, f: F) -> Result<Descriptor<String>, String>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    let descriptor = Descriptor::<String>::from_str(descriptor).map_err(|e| e.to_string())?;
+    descriptor.translate_pk(&mut KeyMapper(f))
+}
+
+struct KeyMapper<F>(F);
+
+impl<F> Translator<String, String, String> for KeyMapper<F>
+where
+    F: FnMut(&str) -> Result<String, String>,
+{
+    fn pk(&mut self, pk: &String) -> Result<String, String> {
+        (self.0)(pk)
+    }
+
+    fn sha256(&mut self, sha256: &String) -> Result<String, String> {
+        Ok(sha256.clone())
+    }
+
+    fn hash256(&mut self, hash256: &String) -> Result<String, String> {
+        Ok(hash256.clone())
+    }
+
+    fn ripemd160(&mut self, ripemd160: &String) -> Result<String, String> {
+        Ok(ripemd160.clone())
+    }
+
+    fn hash160(&mut self, hash160: &String) -> Result<String, String> {
+        Ok(hash160.clone())
+    }
+}
+
+#[derive(PartialEq, Properties)]
+pub struct WalletExportProps {
+    pub wallet: AppWallet,
+}
+
+/// Descriptors and wallet files to use the current wallet in other software
+pub struct WalletExportView {
+    export: Result<WalletExport, String>,
+    files: Vec<(&'static str, String, Result<ObjectUrl, String>)>,
+}
+
+impl WalletExportView {
+    fn load(wallet: &AppWallet) -> Self {
+        let export = WalletExport::new(wallet, "Elephant");
+        let files = match &export {
+            Ok(export) => {
+                let file = |data: Result<String, String>| {
+                    data.map(|data| ObjectUrl::from(Blob::new(data.as_str())))
+                };
+                vec![
+                    (
+                        "Bitcoin Core (importdescriptors)",
+                        "elephant-core.json".to_string(),
+                        file(Ok(export.bitcoin_core())),
+                    ),
+                    (
+                        "Sparrow / Specter",
+                        "elephant-specter.json".to_string(),
+                        file(Ok(export.specter())),
+                    ),
+                    (
+                        "BIP-388 wallet policy",
+                        "elephant-bip388.json".to_string(),
+                        file(export.bip388()),
+                    ),
+                ]
+            }
+            Err(_) => vec![],
+        };
+
+        WalletExportView { export, files }
+    }
+}
+
+impl Component for WalletExportView {
+    type Message = ();
+    type Properties = WalletExportProps;
+
+    fn create(ctx: &Context<Self>) -> Self {
+        Self::load(&ctx.props().wallet)
+    }
+
+    fn changed(&mut self, ctx: &Context<Self>) -> bool {
+        *self = Self::load(&ctx.props().wallet);
+        true
+    }
+
+    fn view(&self, _ctx: &Context<Self>) -> Html {
+        let export = match &self.export {
+            Ok(export) => export,
+            Err(e) => return html! { <div class="text-danger">{ e }</div> },
+        };
+
+        html! {
+            <div class="wallet-export mt-3">
+                <h4>{ "Export" }</h4>
+                <label class="form-label">{ "Receiving descriptor" }</label>
+                <textarea class="form-control daniela-textarea mb-2" rows="3" readonly=true value={export.external.clone()}></textarea>
+                <label class="form-label">{ "Change descriptor" }</label>
+                <textarea class="form-control daniela-textarea mb-2" rows="3" readonly=true value={export.internal.clone()}></textarea>
+                <ul class="list-unstyled">
+                { for self.files.iter().map(|(label, name, url)| html! {
+                    <li>
+                        { format!("{}: ", label) }
+                        { match url {
+                            Ok(url) => html! { <a href={url.to_string()} download={name.clone()}>{ name }</a> },
+                            Err(e) => html! { <span class="text-muted">{ e }</span> },
+                        }}
+                    </li>
+                })}
+                </ul>
+                <div class="form-text">{ "Any of these files can be imported back in the \"Wallet creator\"" }</div>
+            </div>
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use bdk::bitcoin::secp256k1::Secp256k1;
+    use bdk::bitcoin::util::bip32::{ExtendedPrivKey, ExtendedPubKey};
+    use bdk::bitcoin::Network;
+    use bdk::miniscript::DescriptorPublicKey;
+
+    use super::*;
+
+    /// `[fingerprint]tpub` of a master key generated from `seed`
+    fn xpub(seed: u8) -> String {
+        let secp = Secp256k1::new();
+        let xprv = ExtendedPrivKey::new_master(Network::Testnet, &[seed; 32]).unwrap();
+        let xpub = ExtendedPubKey::from_priv(&secp, &xprv);
+        format!("[{}]{}", xprv.fingerprint(&secp), xpub)
+    }
+
+    /// Public descriptor with checksum, the way the wallet returns it
+    fn descriptor(desc: &str) -> String {
+        Descriptor::<DescriptorPublicKey>::from_str(desc)
+            .unwrap()
+            .to_string()
+    }
+
+    fn exported(external: &str) -> WalletExport {
+        WalletExport {
+            name: "Test".to_string(),
+            external: descriptor(external),
+            internal: change_descriptor(external)
+                .map(|d| descriptor(&d))
+                .unwrap_or_else(|| descriptor(external)),
+        }
+    }
+
+    fn multi_key() -> String {
+        format!(
+            "tr({},{{pk({a}/0/*),and_v(v:pk({b}/0/*),pk({c}/0/*))}})",
+            UNSPENDABLE_KEY,
+            a = xpub(1),
+            b = xpub(2),
+            c = xpub(3),
+        )
+    }
+
+    #[test]
+    fn bip388_policy() {
+        let export = exported(&multi_key());
+        let policy: Value = serde_json::from_str(&export.bip388().unwrap()).unwrap();
+
+        assert_eq!(policy["name"], "Test");
+        assert_eq!(
+            policy["descriptor_template"],
+            "tr(@0,{pk(@1/**),and_v(v:pk(@2/**),pk(@3/**))})"
+        );
+        assert_eq!(
+            policy["keys_info"],
+            json!([UNSPENDABLE_KEY, xpub(1), xpub(2), xpub(3)])
+        );
+
+        // Keys used twice have a single placeholder
+        let twice = format!(
+            "wsh(or_d(pk({a}/0/*),and_v(v:pk({a}/0/*),older(10))))",
+            a = xpub(1)
+        );
+        let policy: Value = serde_json::from_str(&exported(&twice).bip388().unwrap()).unwrap();
+        assert_eq!(
+            policy["descriptor_template"],
+            "wsh(or_d(pk(@0/**),and_v(v:pk(@0/**),older(10))))"
+        );
+
+        let other_path = format!("tr({}/1/*)", xpub(1));
+        assert!(exported(&other_path).bip388().is_err());
+    }
+
+    #[test]
+    fn import_exports() {
+        let export = exported(&multi_key());
+        assert_ne!(export.internal, export.external);
+        for file in [
+            export.bip388().unwrap(),
+            export.bitcoin_core(),
+            export.specter(),
+        ] {
+            assert_eq!(
+                import_wallet_file(&file),
+                Some(Ok(export.external.clone())),
+                "{}",
+                file
+            );
+        }
+
+        let unranged = exported(&format!("tr({})", UNSPENDABLE_KEY));
+        for file in [
+            unranged.bip388().unwrap(),
+            unranged.bitcoin_core(),
+            unranged.specter(),
+        ] {
+            assert_eq!(
+                import_wallet_file(&file),
+                Some(Ok(unranged.external.clone()))
+            );
+        }
+    }
+
+    #[test]
+    fn invalid_wallet_files() {
+        assert_eq!(import_wallet_file(&descriptor(&multi_key())), None);
+        assert_eq!(import_wallet_file("not json"), None);
+        for file in [
+            json!(42),
+            json!([]),
+            json!([{ "desc": "tr(A)", "internal": true }]),
+            json!({ "label": "Test" }),
+            json!({ "descriptor_template": "tr(@0/**)" }),
+            json!({ "descriptor_template": "tr(@1/**)", "keys_info": [xpub(1)] }),
+            json!({ "descriptor_template": "tr(@0/<1;0>/*)", "keys_info": [xpub(1)] }),
+        ] {
+            assert!(
+                import_wallet_file(&file.to_string()).unwrap().is_err(),
+                "{}",
+                file
+            );
+        }
+
+        let bip388 = json!({ "descriptor_template": "tr(@0/<0;1>/*)", "keys_info": [xpub(1)] });
+        assert_eq!(
+            from_bip388(&bip388),
+            Ok(descriptor(&format!("tr({}/0/*)", xpub(1))))
+        );
+    }
+
+    #[test]
+    fn change_descriptors() {
+        let receive = multi_key();
+        let change = change_descriptor(&receive).unwrap();
+        assert_eq!(
+            descriptor(&change),
+            descriptor(&receive.replace("/0/*", "/1/*"))
+        );
+
+        // Some keys without a receiving path are kept as they are
+        let mixed = format!("tr({},pk({}/0/*))", UNSPENDABLE_KEY, xpub(1));
+        assert_eq!(
+            change_descriptor(&mixed).map(|d| descriptor(&d)),
+            Some(descriptor(&format!(
+                "tr({},pk({}/1/*))",
+                UNSPENDABLE_KEY,
+                xpub(1)
+            )))
+        );
+
+        for unranged in [
+            format!("tr({})", UNSPENDABLE_KEY),
+            format!("wsh(pk({}))", xpub(1)),
+            format!("wsh(pk({}/0/5))", xpub(1)),
+        ] {
+            assert_eq!(change_descriptor(&unranged), None, "{}", unranged);
+        }
+    }
+}
@@ -0,0 +1,234 @@
+use bdk::Balance;
+use serde::{Deserialize, Serialize};
+
+use crate::app_wallet::BackendSettings;
+use crate::AppWallet;
+
+/// Name of the wallet built from the design in the wallet creator, until it's kept
+pub const DRAFT_NAME: &str = "Wallet creator";
+
+/// A transaction of an open wallet, as shown in the history
+#[derive(Clone, Debug, PartialEq)]
+pub struct WalletTx {
+    pub txid: String,
+    /// Received minus sent, in sats
+    pub amount: i64,
+    /// `None` while unconfirmed
+    pub height: Option<u32>,
+}
+
+/// Descriptors a wallet is built from, once the policy is compiled
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct WalletDescriptors {
+    pub external: String,
+    pub internal: Option<String>,
+}
+
+/// A wallet open in the app, with its own sync state
+#[derive(Clone, PartialEq)]
+pub struct OpenWallet {
+    pub id: usize,
+    pub name: String,
+    /// Policy or descriptor the wallet was built from
+    pub source: String,
+    pub descriptors: WalletDescriptors,
+    pub settings: BackendSettings,
+    pub wallet: AppWallet,
+    pub is_loading: bool,
+    pub is_synced: bool,
+    /// Error of the last sync, if it failed
+    pub sync_error: Option<String>,
+    /// Tip of the chain at the last sync
+    pub synced_height: Option<u32>,
+    pub balance: Balance,
+    pub transactions: Vec<WalletTx>,
+}
+
+impl OpenWallet {
+    fn new(
+        id: usize,
+        name: String,
+        source: String,
+        descriptors: WalletDescriptors,
+        settings: BackendSettings,
+        wallet: AppWallet,
+    ) -> Self {
+        OpenWallet {
+            id,
+            name,
+            source,
+            descriptors,
+            settings,
+            wallet,
+            is_loading: false,
+            is_synced: false,
+            sync_error: None,
+            synced_height: None,
+            balance: Balance::default(),
+            transactions: vec![],
+        }
+    }
+
+    /// Reads the balance and the transactions after a sync
+    pub fn refresh(&mut self) {
+        let inner = self.wallet.borrow();
+        self.balance = inner.get_balance().unwrap();
+
+        let mut transactions = inner.list_transactions(false).unwrap();
+        transactions.sort_by(|a, b| {
+            b.confirmation_time
+                .as_ref()
+                .map(|t| t.height)
+                .cmp(&a.confirmation_time.as_ref().map(|t| t.height))
+        });
+        self.transactions = transactions
+            .into_iter()
+            .map(|tx| WalletTx {
+                txid: tx.txid.to_string(),
+                amount: tx.received as i64 - tx.sent as i64,
+                height: tx.confirmation_time.map(|t| t.height),
+            })
+            .collect();
+        drop(inner);
+        self.synced_height = self.wallet.synced_height();
+        self.is_loading = false;
+        self.is_synced = true;
+        self.sync_error = None;
+    }
+
+    /// Keeps the data of the previous sync, if any
+    pub fn sync_failed(&mut self, error: String) {
+        self.is_loading = false;
+        self.sync_error = Some(error);
+    }
+
+    /// Transactions in the format used by the home tab
+    pub fn history(&self) -> Vec<(String, i64)> {
+        self.transactions
+            .iter()
+            .map(|tx| (tx.txid.clone(), tx.amount))
+            .collect()
+    }
+}
+
+/// Wallets open in the app. The wallet built from the wallet creator is the "draft": it's replaced
+/// every time the design changes, until it's kept under a name.
+#[derive(Default)]
+pub struct Wallets {
+    list: Vec<OpenWallet>,
+    current: Option<usize>,
+    draft: Option<usize>,
+    next_id: usize,
+}
+
+impl Wallets {
+    pub fn list(&self) -> &[OpenWallet] {
+        &self.list
+    }
+
+    pub fn current(&self) -> Option<&OpenWallet> {
+        self.current.and_then(|id| self.get(id))
+    }
+
+    pub fn get(&self, id: usize) -> Option<&OpenWallet> {
+        self.list.iter().find(|w| w.id == id)
+    }
+
+    pub fn get_mut(&mut self, id: usize) -> Option<&mut OpenWallet> {
+        self.list.iter_mut().find(|w| w.id == id)
+    }
+
+    pub fn current_id(&self) -> Option<usize> {
+        self.current
+    }
+
+    pub fn draft(&self) -> Option<usize> {
+        self.draft
+    }
+
+    /// Finds an open wallet with the same descriptors and backend, whose sync data can be reused
+    pub fn find(
+        &self,
+        descriptors: &WalletDescriptors,
+        settings: &BackendSettings,
+    ) -> Option<usize> {
+        self.list
+            .iter()
+            .find(|w| &w.descriptors == descriptors && &w.settings == settings)
+            .map(|w| w.id)
+    }
+
+    pub fn select(&mut self, id: usize) {
+        if self.get(id).is_some() {
+            self.current = Some(id);
+        }
+    }
+
+    /// Replaces the draft with a new wallet, and opens it
+    pub fn set_draft(
+        &mut self,
+        source: String,
+        descriptors: WalletDescriptors,
+        settings: BackendSettings,
+        wallet: AppWallet,
+    ) {
+        let id = self.next_id;
+        self.next_id += 1;
+
+        let draft = OpenWallet::new(
+            id,
+            DRAFT_NAME.to_string(),
+            source,
+            descriptors,
+            settings,
+            wallet,
+        );
+        match self
+            .draft
+            .and_then(|d| self.list.iter().position(|w| w.id == d))
+        {
+            Some(i) => self.list[i] = draft,
+            None => self.list.push(draft),
+        }
+        self.draft = Some(id);
+        self.current = Some(id);
+    }
+
+    pub fn clear_draft(&mut self) {
+        if let Some(draft) = self.draft.take() {
+            self.close(draft);
+        }
+    }
+
+    /// Keeps the draft under a name: the wallet creator will start a new draft on the next change
+    pub fn keep_draft(&mut self, name: String) {
+        if let Some(draft) = self.draft.take() {
+            if let Some(wallet) = self.get_mut(draft) {
+                wallet.name = name;
+            }
+        }
+    }
+
+    /// Closes the wallets that know private keys
+    pub fn close_signing(&mut self) {
+        let signing = self
+            .list
+            .iter()
+            .filter(|w| !w.wallet.is_watch_only())
+            .map(|w| w.id)
+            .collect::<Vec<_>>();
+        for id in signing {
+            self.close(id);
+        }
+    }
+
+    pub fn close(&mut self, id: usize) {
+        self.list.retain(|w| w.id != id);
+        if self.draft == Some(id) {
+            self.draft = None;
+        }
+        if self.current == Some(id) {
+            self.current = self.list.last().map(|w| w.id);
+        }
+    }
+}